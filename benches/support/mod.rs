@@ -0,0 +1,60 @@
+// Synthetic source trees shared by the throughput benchmarks, which are timed by criterion.
+// The sizes come from the environment so a run can be scaled without editing code:
+//
+//   ZIPRS_BENCH_FILES      number of files in the tree (default 2000)
+//   ZIPRS_BENCH_FILE_SIZE  size of each file in bytes (default 16384)
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Shape of the generated tree
+pub struct TreeSpec {
+    pub file_count: usize,
+    pub file_size: usize,
+}
+
+impl TreeSpec {
+    pub fn from_env() -> Self {
+        TreeSpec {
+            file_count: env_or("ZIPRS_BENCH_FILES", 2000),
+            file_size: env_or("ZIPRS_BENCH_FILE_SIZE", 16 * 1024),
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        (self.file_count * self.file_size) as u64
+    }
+}
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Write `spec.file_count` files under `root`, spread over nested directories of 100 files
+// each. Contents are deterministic and half repetitive text, half pseudo-random bytes, so
+// every compression method has something to do.
+pub fn generate_tree(root: &Path, spec: &TreeSpec) -> io::Result<()> {
+    let mut state: u32 = 0x9e37_79b9;
+    for i in 0..spec.file_count {
+        let dir = root.join(format!("group{:03}", i / 100));
+        fs::create_dir_all(&dir)?;
+        let mut content = Vec::with_capacity(spec.file_size);
+        while content.len() < spec.file_size / 2 {
+            content.extend_from_slice(format!("line {} of file {}\n", content.len(), i).as_bytes());
+        }
+        while content.len() < spec.file_size {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            content.push(state as u8);
+        }
+        content.truncate(spec.file_size);
+        fs::write(dir.join(format!("file{:05}.dat", i)), content)?;
+    }
+    Ok(())
+}
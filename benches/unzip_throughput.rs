@@ -0,0 +1,39 @@
+// Throughput of `unzip_files` extracting a Deflate archive of a synthetic tree.
+// Run with `cargo bench --bench unzip_throughput`; see `support` for the size knobs.
+
+mod support;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tempfile::tempdir;
+use ziprs::unzip::unzip_files;
+use ziprs::zip::{zip_files, Compression};
+
+use support::{generate_tree, TreeSpec};
+
+fn unzip_throughput(c: &mut Criterion) {
+    let spec = TreeSpec::from_env();
+    let work_dir = tempdir().expect("temp dir");
+    let tree = work_dir.path().join("tree");
+    generate_tree(&tree, &spec).expect("generate tree");
+    let archive = work_dir.path().join("tree.zip");
+    zip_files(&archive, &[tree], Compression::Deflate).expect("zip tree");
+
+    let mut group = c.benchmark_group("unzip");
+    group.throughput(Throughput::Bytes(spec.total_bytes()));
+    group.sample_size(10);
+    // Each run extracts into a fresh directory, created and removed outside the timed section
+    group.bench_function("deflate", |b| {
+        b.iter_batched(
+            || tempdir().expect("temp dir"),
+            |dst| {
+                unzip_files(&archive, dst.path()).expect("unzip tree");
+                dst
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, unzip_throughput);
+criterion_main!(benches);
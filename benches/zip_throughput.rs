@@ -0,0 +1,73 @@
+// Throughput of `zip_files` on a synthetic tree, once per compression method, and of
+// buffered against unbuffered output on many tiny files.
+// Run with `cargo bench --bench zip_throughput`; see `support` for the size knobs.
+
+mod support;
+
+use clap::ValueEnum;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tempfile::tempdir;
+use ziprs::zip::{
+    zip_files, zip_files_with_options, Compression, ZipOptions, DEFAULT_WRITE_BUFFER_SIZE,
+};
+
+use support::{generate_tree, TreeSpec};
+
+fn zip_throughput(c: &mut Criterion) {
+    let spec = TreeSpec::from_env();
+    let work_dir = tempdir().expect("temp dir");
+    let tree = work_dir.path().join("tree");
+    generate_tree(&tree, &spec).expect("generate tree");
+
+    let mut group = c.benchmark_group("zip");
+    group.throughput(Throughput::Bytes(spec.total_bytes()));
+    // Each run archives the whole tree, so criterion's default 100 samples take too long
+    group.sample_size(10);
+    for &compression in Compression::value_variants() {
+        let name = compression.to_possible_value().map_or_else(
+            || format!("{:?}", compression),
+            |v| v.get_name().to_string(),
+        );
+        let dst = work_dir.path().join(format!("{}.zip", name));
+        group.bench_function(&name, |b| {
+            b.iter(|| zip_files(&dst, std::slice::from_ref(&tree), compression).expect("zip tree"))
+        });
+    }
+    group.finish();
+}
+
+// Many tiny entries make the archive output a stream of small writes, which is what
+// `write_buffer_size` batches up. The tree shape is fixed so runs stay comparable.
+fn zip_write_buffer(c: &mut Criterion) {
+    let spec = TreeSpec {
+        file_count: 10_000,
+        file_size: 16,
+    };
+    let work_dir = tempdir().expect("temp dir");
+    let tree = work_dir.path().join("tree");
+    generate_tree(&tree, &spec).expect("generate tree");
+
+    let mut group = c.benchmark_group("zip_write_buffer");
+    group.sample_size(10);
+    for (name, write_buffer_size) in [
+        ("unbuffered", 0),
+        ("64k", 64 * 1024),
+        ("default", DEFAULT_WRITE_BUFFER_SIZE),
+    ] {
+        let dst = work_dir.path().join(format!("{}.zip", name));
+        let options = ZipOptions {
+            write_buffer_size,
+            ..Default::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                zip_files_with_options(&dst, std::slice::from_ref(&tree), &options)
+                    .expect("zip tree")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, zip_throughput, zip_write_buffer);
+criterion_main!(benches);
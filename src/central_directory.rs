@@ -0,0 +1,86 @@
+// Raw access to the central directory of an archive, for the rewrites the zip crate has no
+// API for: encrypting entries with ZipCrypto, moving sizes into data descriptors and resolving
+// entries that share a name.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+
+// Length of a central directory header before its name, extra field and comment
+pub(crate) const CENTRAL_HEADER_LEN: usize = 46;
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub(crate) fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.to_string())
+}
+
+pub(crate) fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+pub(crate) fn write_u16(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(bytes: &mut [u8], at: usize, value: u32) {
+    bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// Every header of a central directory, followed by the end records
+pub(crate) struct CentralDirectory {
+    // One raw central directory header per entry, in archive order
+    pub(crate) records: Vec<Vec<u8>>,
+    // End of central directory record, preceded by the zip64 ones if any, and the comment
+    pub(crate) end_records: Vec<u8>,
+}
+
+impl CentralDirectory {
+    // Read the central directory starting at `start`, as the zip crate reports it, through
+    // to the end of the input
+    pub(crate) fn read<R: Read + Seek>(reader: &mut R, start: u64) -> io::Result<Self> {
+        let mut tail = Vec::new();
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_to_end(&mut tail)?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while tail.get(pos..pos + 4) == Some(&CENTRAL_HEADER_SIGNATURE.to_le_bytes()[..]) {
+            let header = tail
+                .get(pos..pos + CENTRAL_HEADER_LEN)
+                .ok_or_else(|| invalid("Truncated central directory header"))?;
+            let record_end = pos
+                + CENTRAL_HEADER_LEN
+                + read_u16(header, 28) as usize
+                + read_u16(header, 30) as usize
+                + read_u16(header, 32) as usize;
+            let record = tail
+                .get(pos..record_end)
+                .ok_or_else(|| invalid("Truncated central directory header"))?;
+            records.push(record.to_vec());
+            pos = record_end;
+        }
+        Ok(CentralDirectory {
+            records,
+            end_records: tail[pos..].to_vec(),
+        })
+    }
+
+    // Whether the sizes and offsets of the archive live in zip64 records
+    pub(crate) fn is_zip64(&self) -> bool {
+        self.end_records
+            .starts_with(&ZIP64_END_SIGNATURE.to_le_bytes())
+    }
+}
+
+// Raw name of the entry a central directory header describes
+pub(crate) fn record_name(record: &[u8]) -> &[u8] {
+    &record[CENTRAL_HEADER_LEN..CENTRAL_HEADER_LEN + read_u16(record, 28) as usize]
+}
@@ -0,0 +1,120 @@
+// Streaming layout for `ZipOptions::streaming_mode`: every file entry's local header carries
+// general purpose flag bit 3 with zeroed CRC and sizes, and its data is followed by a data
+// descriptor holding them, the way a writer that can't seek back lays an archive out. The
+// zip crate only writes entries whose local headers are filled in, so the finished archive
+// is rewritten into this form.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::central_directory::{
+    invalid, read_u16, read_u32, record_name, unsupported, write_u16, write_u32, CentralDirectory,
+};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+
+// General purpose flag telling readers the CRC and sizes follow the data
+const DATA_DESCRIPTOR_FLAG: u16 = 1 << 3;
+
+fn too_large() -> io::Error {
+    unsupported("Streaming mode is only supported for archives under 4 GiB")
+}
+
+// Rewrite the finished archive at `path` so every file entry uses a data descriptor.
+// Directory entries, which hold no data, are left as they are. Archives that need zip64
+// records (4 GiB or more) are refused, since their descriptors would need 8-byte sizes.
+pub(crate) fn add_data_descriptors(path: &Path) -> io::Result<()> {
+    let mut source = BufReader::new(File::open(path)?);
+    let central_directory_start = ZipArchive::new(source.get_mut())?.central_directory_start();
+    let directory = CentralDirectory::read(&mut source, central_directory_start)?;
+    if directory.is_zip64() {
+        return Err(too_large());
+    }
+
+    let temp_file = tempfile::NamedTempFile::new_in(path.parent().unwrap_or(Path::new(".")))?;
+    let mut output = BufWriter::new(temp_file.as_file());
+    let mut written: u64 = 0;
+    let mut chunk = vec![0u8; 64 * 1024];
+    let CentralDirectory {
+        mut records,
+        mut end_records,
+    } = directory;
+    for record in &mut records {
+        let flags = read_u16(record, 8);
+        let crc32 = read_u32(record, 16);
+        let compressed_size = read_u32(record, 20);
+        let uncompressed_size = read_u32(record, 24);
+        let local_offset = read_u32(record, 42);
+        if compressed_size == u32::MAX || uncompressed_size == u32::MAX {
+            return Err(too_large());
+        }
+        let is_dir = record_name(record).ends_with(b"/");
+
+        let mut local_header = [0u8; 30];
+        source.seek(SeekFrom::Start(u64::from(local_offset)))?;
+        source.read_exact(&mut local_header)?;
+        if read_u32(&local_header, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(invalid("Missing local file header"));
+        }
+        let mut name_and_extra =
+            vec![0u8; read_u16(&local_header, 26) as usize + read_u16(&local_header, 28) as usize];
+        source.read_exact(&mut name_and_extra)?;
+
+        let new_offset = u32::try_from(written)
+            .ok()
+            .filter(|&offset| offset != u32::MAX)
+            .ok_or_else(too_large)?;
+        write_u32(record, 42, new_offset);
+        if !is_dir {
+            write_u16(&mut local_header, 6, flags | DATA_DESCRIPTOR_FLAG);
+            write_u16(record, 8, flags | DATA_DESCRIPTOR_FLAG);
+            write_u32(&mut local_header, 14, 0);
+            write_u32(&mut local_header, 18, 0);
+            write_u32(&mut local_header, 22, 0);
+        }
+        output.write_all(&local_header)?;
+        output.write_all(&name_and_extra)?;
+
+        let mut remaining = u64::from(compressed_size);
+        while remaining > 0 {
+            let len = remaining.min(chunk.len() as u64) as usize;
+            source.read_exact(&mut chunk[..len])?;
+            output.write_all(&chunk[..len])?;
+            remaining -= len as u64;
+        }
+        written += (local_header.len() + name_and_extra.len()) as u64 + u64::from(compressed_size);
+
+        if !is_dir {
+            let mut descriptor = [0u8; 16];
+            write_u32(&mut descriptor, 0, DATA_DESCRIPTOR_SIGNATURE);
+            write_u32(&mut descriptor, 4, crc32);
+            write_u32(&mut descriptor, 8, compressed_size);
+            write_u32(&mut descriptor, 12, uncompressed_size);
+            output.write_all(&descriptor)?;
+            written += descriptor.len() as u64;
+        }
+    }
+
+    // The records keep their length, so only the offset of the central directory moves
+    let central_directory_offset = u32::try_from(written)
+        .ok()
+        .filter(|&offset| offset != u32::MAX)
+        .ok_or_else(too_large)?;
+    let offset_field = end_records
+        .get_mut(16..20)
+        .ok_or_else(|| invalid("Truncated end of central directory record"))?;
+    offset_field.copy_from_slice(&central_directory_offset.to_le_bytes());
+    for record in &records {
+        output.write_all(record)?;
+    }
+    output.write_all(&end_records)?;
+    output.flush()?;
+    drop(output);
+
+    fs::set_permissions(temp_file.path(), fs::metadata(path)?.permissions())?;
+    temp_file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
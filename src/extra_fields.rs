@@ -0,0 +1,140 @@
+// Encoding and decoding of the zip extra fields ziprs uses to carry metadata that the
+// core zip format has no room for.
+
+// Info-ZIP "new Unix" extra field holding the owner uid/gid
+pub(crate) const UNIX_OWNERSHIP_ID: u16 = 0x7875;
+
+// Encode a uid/gid pair as an Info-ZIP "new Unix" field body (version 1, 4-byte ids)
+pub(crate) fn encode_ownership(uid: u32, gid: u32) -> Box<[u8]> {
+    let mut data = Vec::with_capacity(11);
+    data.push(1);
+    data.push(4);
+    data.extend_from_slice(&uid.to_le_bytes());
+    data.push(4);
+    data.extend_from_slice(&gid.to_le_bytes());
+    data.into_boxed_slice()
+}
+
+// Decode an Info-ZIP "new Unix" field body. Other tools may write ids of any width up to 8 bytes.
+pub(crate) fn decode_ownership(data: &[u8]) -> Option<(u32, u32)> {
+    fn read_id(data: &[u8]) -> Option<(u32, &[u8])> {
+        let (&size, rest) = data.split_first()?;
+        let size = size as usize;
+        if size > 8 || rest.len() < size {
+            return None;
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&rest[..size]);
+        let id = u32::try_from(u64::from_le_bytes(bytes)).ok()?;
+        Some((id, &rest[size..]))
+    }
+
+    let (&version, rest) = data.split_first()?;
+    if version != 1 {
+        return None;
+    }
+    let (uid, rest) = read_id(rest)?;
+    let (gid, _) = read_id(rest)?;
+    Some((uid, gid))
+}
+
+// Find the body of the first extra field with the given header id
+pub(crate) fn find_field(extra: &[u8], header_id: u16) -> Option<&[u8]> {
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let id = u16::from_le_bytes([rest[0], rest[1]]);
+        let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let body = rest.get(4..4 + len)?;
+        if id == header_id {
+            return Some(body);
+        }
+        rest = &rest[4 + len..];
+    }
+    None
+}
+
+// Private ziprs field marking an entry whose content lives in another entry of the archive
+pub(crate) const ZIPRS_LINK_ID: u16 = 0x6c7a;
+
+// How an entry carrying a link field relates to the entry it points to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LinkKind {
+    // Identical content stored once by `ZipOptions::dedup`
+    Duplicate,
+    // Another name for the same inode, recorded by `ZipOptions::preserve_hardlinks`
+    Hardlink,
+}
+
+// Encode a link field body: one kind byte followed by the UTF-8 name of the target entry
+pub(crate) fn encode_link(kind: LinkKind, target: &str) -> Box<[u8]> {
+    let kind_byte = match kind {
+        LinkKind::Duplicate => 0,
+        LinkKind::Hardlink => 1,
+    };
+    let mut data = Vec::with_capacity(1 + target.len());
+    data.push(kind_byte);
+    data.extend_from_slice(target.as_bytes());
+    data.into_boxed_slice()
+}
+
+pub(crate) fn decode_link(data: &[u8]) -> Option<(LinkKind, String)> {
+    let (&kind_byte, target) = data.split_first()?;
+    let kind = match kind_byte {
+        0 => LinkKind::Duplicate,
+        1 => LinkKind::Hardlink,
+        _ => return None,
+    };
+    let target = std::str::from_utf8(target).ok()?;
+    Some((kind, target.to_string()))
+}
+
+// Private ziprs field holding the extended attributes of a file
+pub(crate) const ZIPRS_XATTR_ID: u16 = 0x787a;
+
+// Encode extended attributes as consecutive (u16 name length, name, u16 value length, value)
+// records. Callers keep the total within the 64 KiB an extra field can hold.
+pub(crate) fn encode_xattrs(xattrs: &[(Vec<u8>, Vec<u8>)]) -> Box<[u8]> {
+    let mut data = Vec::new();
+    for (name, value) in xattrs {
+        data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        data.extend_from_slice(name);
+        data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        data.extend_from_slice(value);
+    }
+    data.into_boxed_slice()
+}
+
+pub(crate) fn decode_xattrs(data: &[u8]) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    fn read_chunk(data: &[u8]) -> Option<(&[u8], &[u8])> {
+        let len = u16::from_le_bytes([*data.first()?, *data.get(1)?]) as usize;
+        let chunk = data.get(2..2 + len)?;
+        Some((chunk, &data[2 + len..]))
+    }
+
+    let mut xattrs = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (name, after_name) = read_chunk(rest)?;
+        let (value, after_value) = read_chunk(after_name)?;
+        xattrs.push((name.to_vec(), value.to_vec()));
+        rest = after_value;
+    }
+    Some(xattrs)
+}
+
+// Private ziprs field holding the creation (birth) time of a file
+pub(crate) const ZIPRS_BIRTHTIME_ID: u16 = 0x627a;
+
+// Encode a creation time as unix seconds (i64) followed by nanoseconds (u32)
+pub(crate) fn encode_birthtime(seconds: i64, nanos: u32) -> Box<[u8]> {
+    let mut data = Vec::with_capacity(12);
+    data.extend_from_slice(&seconds.to_le_bytes());
+    data.extend_from_slice(&nanos.to_le_bytes());
+    data.into_boxed_slice()
+}
+
+pub(crate) fn decode_birthtime(data: &[u8]) -> Option<(i64, u32)> {
+    let seconds = i64::from_le_bytes(data.get(..8)?.try_into().ok()?);
+    let nanos = u32::from_le_bytes(data.get(8..12)?.try_into().ok()?);
+    (nanos < 1_000_000_000).then_some((seconds, nanos))
+}
@@ -1,14 +1,52 @@
 use pyo3::prelude::*;
 
+mod central_directory;
+mod data_descriptor;
+mod extra_fields;
+pub mod report;
 pub mod unzip;
 pub mod zip;
+mod zipcrypto;
 
-pub use unzip::unzip_files_pywrapper;
-pub use zip::zip_files_pywrapper;
+pub use unzip::{
+    check_safe_pywrapper, diff_archives_pywrapper, extract_entries_pywrapper,
+    extract_entry_to_pywrapper, for_each_entry_pywrapper, is_zip_pywrapper, list_archive_pywrapper,
+    list_top_level_pywrapper, read_entry_comment_pywrapper, read_entry_lines_pywrapper,
+    total_uncompressed_size_pywrapper, unzip_files_pywrapper, unzip_large_parallel_pywrapper,
+    unzip_many_pywrapper, verify_manifest_pywrapper,
+};
+pub use zip::{
+    chmod_entry_pywrapper, estimate_size_pywrapper, merge_archives_pywrapper, recompress_pywrapper,
+    supported_methods_pywrapper, update_archive_pywrapper, zip_files_multi_pywrapper,
+    zip_files_pywrapper, zip_large_parallel_pywrapper, zip_to_fileobj_pywrapper,
+};
 
 #[pymodule]
 fn ziprs(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(zip_files_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(zip_files_multi_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(zip_to_fileobj_pywrapper, m)?)?;
     m.add_function(wrap_pyfunction!(unzip_files_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(unzip_many_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(is_zip_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(list_archive_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_entry_to_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_entries_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(list_top_level_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(check_safe_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(read_entry_comment_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_manifest_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(diff_archives_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(for_each_entry_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(read_entry_lines_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(total_uncompressed_size_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(zip_large_parallel_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(recompress_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(supported_methods_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate_size_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_archives_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(update_archive_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(chmod_entry_pywrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(unzip_large_parallel_pywrapper, m)?)?;
     Ok(())
 }
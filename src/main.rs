@@ -1,14 +1,27 @@
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
 use std::path::PathBuf;
 
 use ziprs::{
-    unzip::unzip_files,
-    zip::{zip_files, Compression},
+    unzip::{
+        archive_info, available_space, extract_single_entry_to, list_archive,
+        total_uncompressed_size, unzip_files_with_options, DuplicateEntries, ExtractOrder,
+        OversizePolicy, UnzipOptions,
+    },
+    zip::{
+        zip_files_with_options, ArchiveFormat, Compression, DuplicatePolicy, EncryptionMethod,
+        EntryOrder, LineEnding, TimestampRounding, ZipOptions,
+    },
 };
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
+    /// Print more detail on stderr (-v for a summary, -vv for every entry; RUST_LOG overrides)
+    #[clap(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -28,6 +41,164 @@ enum Commands {
         /// Compression method to use
         #[clap(short, long)]
         compression: Compression,
+
+        /// Skip hidden files and directories (names starting with '.')
+        #[clap(long)]
+        skip_hidden: bool,
+
+        /// Honor a .zipignore file at the root of each input directory
+        #[clap(long)]
+        use_zipignore: bool,
+
+        /// Record the owner uid/gid of every entry
+        #[clap(long)]
+        preserve_ownership: bool,
+
+        /// Store only 0o755 (executable) or 0o644 modes, for archives used across platforms
+        #[clap(long)]
+        portable_permissions: bool,
+
+        /// Allow the output archive to be included if it lies inside an input directory
+        #[clap(long)]
+        include_self: bool,
+
+        /// Store identical files only once (restored by ziprs unzip)
+        #[clap(long)]
+        dedup: bool,
+
+        /// Store additional hard links to a file as links to its first name
+        #[clap(long)]
+        preserve_hardlinks: bool,
+
+        /// Write a JSON report of the archived entries to this path
+        #[clap(long)]
+        report: Option<PathBuf>,
+
+        /// Write a newline-delimited JSON index of the entries to this path
+        #[clap(long)]
+        index: Option<PathBuf>,
+
+        /// What to do when two inputs map to the same path inside the archive
+        #[clap(long, value_enum, default_value_t = DuplicatePolicy::Error)]
+        on_duplicate: DuplicatePolicy,
+
+        /// Order of the entries written for each input directory
+        #[clap(long, value_enum, default_value_t = EntryOrder::DirsFirst)]
+        entry_order: EntryOrder,
+
+        /// Build the archive in this directory, then move it to the output path
+        #[clap(long)]
+        temp_dir: Option<PathBuf>,
+
+        /// Compress each file with this many zstd worker threads (zstd compression only)
+        #[clap(long)]
+        zstd_workers: Option<u32>,
+
+        /// Only archive files modified at or after this unix time (seconds)
+        #[clap(long)]
+        modified_since: Option<i64>,
+
+        /// Leave out directories that contain no archived files
+        #[clap(long)]
+        prune_empty_dirs: bool,
+
+        /// Convert the line endings of text files (by extension) to this one
+        #[clap(long, value_enum)]
+        text_eol: Option<LineEnding>,
+
+        /// Skip files smaller than this many bytes
+        #[clap(long)]
+        min_file_size: Option<u64>,
+
+        /// Skip files larger than this many bytes
+        #[clap(long)]
+        max_file_size: Option<u64>,
+
+        /// Store files given by absolute path under that path, minus the leading slash
+        #[clap(long)]
+        preserve_absolute: bool,
+
+        /// Archive format to write
+        #[clap(long, value_enum, default_value_t = ArchiveFormat::Zip)]
+        format: ArchiveFormat,
+
+        /// Number of threads reading input files (defaults to one per CPU)
+        #[clap(long)]
+        read_concurrency: Option<usize>,
+
+        /// Record the extended attributes of every file
+        #[clap(long)]
+        preserve_xattrs: bool,
+
+        /// Memory-map large input files instead of reading them (inputs must not change meanwhile)
+        #[clap(long)]
+        use_mmap: bool,
+
+        /// Place every entry under this directory inside the archive
+        #[clap(long)]
+        prefix: Option<String>,
+
+        /// Leave out entries whose archive path matches this gitignore-style glob (repeatable)
+        #[clap(long = "exclude", value_name = "PATTERN")]
+        exclude: Vec<String>,
+
+        /// Drop inputs that lie inside another input instead of failing
+        #[clap(long)]
+        dedup_sources: bool,
+
+        /// Encrypt file contents (with --password or --password-env, or prompts for a password)
+        #[clap(long)]
+        encrypt: bool,
+
+        /// Password to encrypt with. Prefer --password-env, as arguments show up in shell
+        /// history and process lists.
+        #[clap(long)]
+        password: Option<String>,
+
+        /// Read the password to encrypt with from the ZIPRS_PASSWORD environment variable
+        #[clap(long)]
+        password_env: bool,
+
+        /// Record the tool, version, time and host in the archive comment
+        #[clap(long)]
+        embed_metadata: bool,
+
+        /// Fail instead of replacing an existing output file
+        #[clap(long)]
+        no_clobber: bool,
+
+        /// Append a .ziprs-manifest.json entry recording every entry's size and CRC-32
+        #[clap(long)]
+        write_manifest: bool,
+
+        /// Store file mtimes, rounded this way to the 2-second resolution of zip timestamps
+        #[clap(long, value_enum)]
+        timestamp_rounding: Option<TimestampRounding>,
+
+        /// Cipher to encrypt with. zipcrypto is weak and only meant for legacy tools that
+        /// can't read AES.
+        #[clap(long, value_enum, default_value_t = EncryptionMethod::Aes256)]
+        encryption_method: EncryptionMethod,
+
+        /// Name to store the input under when zipping a single file
+        #[clap(long)]
+        single_arcname: Option<String>,
+
+        /// Descend into symlinked directories instead of storing them as empty directories
+        #[clap(long)]
+        follow_symlinks: bool,
+
+        /// Also write <output>.sha256 holding the archive's SHA-256, as sha256sum does
+        #[clap(long)]
+        write_checksum: bool,
+
+        /// Record each file's creation time, where the file system reports one
+        #[clap(long)]
+        preserve_birthtime: bool,
+
+        /// Write each file's CRC and sizes in a data descriptor after its data, as streaming writers do
+        #[clap(long)]
+        streaming_mode: bool,
     },
     /// Unzips a specified archive
     Unzip {
@@ -36,32 +207,488 @@ enum Commands {
         zip_path: PathBuf,
 
         /// Directory to extract files to
-        #[clap(short, long)]
-        output_dir: PathBuf,
+        #[clap(short, long, required_unless_present = "stdout")]
+        output_dir: Option<PathBuf>,
+
+        /// Write a single entry's bytes to stdout instead of extracting to a directory
+        #[clap(long)]
+        stdout: bool,
+
+        /// Entry to write with --stdout (optional when the archive holds a single file)
+        #[clap(requires = "stdout")]
+        entry: Option<String>,
+
+        /// Restore recorded owner uid/gid (only applied when running as root)
+        #[clap(long)]
+        preserve_ownership: bool,
+
+        /// Octal mode applied to every extracted file instead of the archived one
+        #[clap(long, value_parser = parse_octal_mode)]
+        force_mode: Option<u32>,
+
+        /// Octal mode applied to every extracted directory
+        #[clap(long, value_parser = parse_octal_mode)]
+        force_dir_mode: Option<u32>,
+
+        /// Write a JSON report of the extracted entries to this path
+        #[clap(long)]
+        report: Option<PathBuf>,
+
+        /// Leave existing files untouched when their size and CRC-32 match the archive
+        #[clap(long)]
+        skip_identical: bool,
+
+        /// Fail on symlinks pointing outside the output directory instead of warning
+        #[clap(long)]
+        strict_symlinks: bool,
+
+        /// Record extracted entries in this file and skip those already listed (resume)
+        #[clap(long)]
+        ledger: Option<PathBuf>,
+
+        /// Keep extracting after an entry fails, then list every failure
+        #[clap(long)]
+        continue_on_error: bool,
+
+        /// Extract into the output directory directly when every entry shares one top-level directory
+        #[clap(long)]
+        auto_strip_root: bool,
+
+        /// Recreate hard links recorded with `zip --preserve-hardlinks` instead of copying
+        #[clap(long)]
+        preserve_hardlinks: bool,
+
+        /// Retry creating or writing a file this many times after a transient error
+        #[clap(long, default_value_t = 0)]
+        retries: usize,
+
+        /// Only extract entries whose octal mode, masked by --mode-mask, equals this
+        #[clap(long, value_parser = parse_octal_mode)]
+        mode_filter: Option<u32>,
+
+        /// Octal mask applied to entry modes before comparing with --mode-filter
+        #[clap(long, value_parser = parse_octal_mode)]
+        mode_mask: Option<u32>,
+
+        /// Largest uncompressed size in bytes allowed for a single entry
+        #[clap(long)]
+        max_entry_size: Option<u64>,
+
+        /// Refuse archives holding more entries than this
+        #[clap(long)]
+        max_entries: Option<usize>,
+
+        /// Which of several files sharing a name to extract; number extracts them all
+        #[clap(long, value_enum, default_value_t = DuplicateEntries::Last)]
+        on_duplicate: DuplicateEntries,
+
+        /// Warn instead of failing when permissions, ownership or times can't be applied
+        #[clap(long)]
+        tolerant_metadata: bool,
+
+        /// Restore the creation times recorded with --preserve-birthtime (macOS only)
+        #[clap(long)]
+        preserve_birthtime: bool,
+
+        /// Keep setuid, setgid and sticky bits of tar.gz entries (dropped by default)
+        #[clap(long)]
+        preserve_special_bits: bool,
+
+        /// What to do with entries over --max-entry-size
+        #[clap(long, value_enum, default_value_t = OversizePolicy::Error)]
+        oversized_entries: OversizePolicy,
+
+        /// Restore each file's access time from the archive (not kept on noatime mounts)
+        #[clap(long)]
+        preserve_atime: bool,
+
+        /// Number of threads writing extracted files (defaults to one per CPU)
+        #[clap(long)]
+        write_concurrency: Option<usize>,
+
+        /// Restore extended attributes recorded with `zip --preserve-xattrs`
+        #[clap(long)]
+        preserve_xattrs: bool,
+
+        /// Rename files about to be overwritten to <name>.bak first
+        #[clap(long)]
+        backup: bool,
+
+        /// Order to extract entries in; by_dir groups them by parent directory
+        #[clap(long, value_enum, default_value_t = ExtractOrder::Archive)]
+        order: ExtractOrder,
+
+        /// Password to decrypt entries with. Prefer --password-env, as arguments show up in
+        /// shell history and process lists.
+        #[clap(long)]
+        password: Option<String>,
+
+        /// Read the password to decrypt with from the ZIPRS_PASSWORD environment variable
+        #[clap(long)]
+        password_env: bool,
     },
+    /// Lists the entries of an archive
+    List {
+        /// Path to the zip file to list
+        #[clap(required = true)]
+        zip_path: PathBuf,
+
+        /// Show the entries as an indented tree instead of one path per line
+        #[clap(long)]
+        tree: bool,
+    },
+    /// Shows entry count, sizes, compression ratio and comment of an archive
+    Info {
+        /// Path to the zip file to inspect
+        #[clap(required = true)]
+        zip_path: PathBuf,
+    },
+}
+
+// Entry names nested by path component, for `list --tree`. Directories only implied by
+// deeper entries get a node too.
+#[derive(Default)]
+struct TreeNode {
+    is_dir: bool,
+    children: BTreeMap<String, TreeNode>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, name: &str) {
+        let mut node = self;
+        for part in name.split('/').filter(|part| !part.is_empty()) {
+            node.is_dir = true;
+            node = node.children.entry(part.to_string()).or_default();
+        }
+        if name.ends_with('/') {
+            node.is_dir = true;
+        }
+    }
+
+    fn label(name: &str, node: &TreeNode) -> String {
+        if node.is_dir {
+            format!("{}/", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    // Top-level entries flush left, their contents below with box-drawing connectors
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, child) in &self.children {
+            out.push_str(&TreeNode::label(name, child));
+            out.push('\n');
+            child.render_children("", &mut out);
+        }
+        out
+    }
+
+    fn render_children(&self, prefix: &str, out: &mut String) {
+        let count = self.children.len();
+        for (i, (name, child)) in self.children.iter().enumerate() {
+            let last = i + 1 == count;
+            out.push_str(prefix);
+            out.push_str(if last { "└── " } else { "├── " });
+            out.push_str(&TreeNode::label(name, child));
+            out.push('\n');
+            let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            child.render_children(&child_prefix, out);
+        }
+    }
+}
+
+const PASSWORD_ENV_VAR: &str = "ZIPRS_PASSWORD";
+
+// Pick the password to encrypt or decrypt with: the environment variable when --password-env
+// is given, the --password flag otherwise. Either one turns on encryption when zipping; with
+// neither, --encrypt asks for one when stdin is a terminal.
+fn resolve_password(
+    encrypt: bool,
+    password: Option<String>,
+    password_env: bool,
+) -> Result<Option<String>, String> {
+    let password = if password_env {
+        let password = std::env::var(PASSWORD_ENV_VAR)
+            .map_err(|_| format!("--password-env given but {} is not set", PASSWORD_ENV_VAR))?;
+        Some(password)
+    } else {
+        password
+    };
+    let password = match password {
+        None if encrypt && std::io::stdin().is_terminal() => Some(prompt_password()?),
+        None if encrypt => {
+            return Err(format!(
+                "--encrypt needs a password from --password-env ({}) or --password, or a terminal to prompt on",
+                PASSWORD_ENV_VAR
+            ))
+        }
+        password => password,
+    };
+    if password.as_deref() == Some("") {
+        return Err("The password must not be empty".to_string());
+    }
+    Ok(password)
+}
+
+// Ask for the password to encrypt with on the terminal, twice to catch typos, since a
+// mistyped one makes the archive unreadable
+fn prompt_password() -> Result<String, String> {
+    let prompt_failed = |e: std::io::Error| format!("Failed to read the password: {}", e);
+    let password = rpassword::prompt_password("Password: ").map_err(prompt_failed)?;
+    let confirmation = rpassword::prompt_password("Confirm password: ").map_err(prompt_failed)?;
+    if password != confirmation {
+        return Err("The passwords don't match".to_string());
+    }
+    Ok(password)
+}
+
+// Parse a permission mode written in octal, e.g. "644" or "0o644"
+fn parse_octal_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    u32::from_str_radix(digits, 8).map_err(|e| format!("invalid octal mode '{}': {}", s, e))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    // Warnings are always shown, -v adds info and -vv debug. RUST_LOG overrides them.
+    let default_level = match cli.verbose {
+        0 => "warn",
+        1 => "info",
+        _ => "debug",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level))
+        .format_timestamp(None)
+        .format_target(false)
+        .try_init()?;
 
     match cli.command {
         Commands::Zip {
             input_paths,
             output_path,
             compression,
+            skip_hidden,
+            use_zipignore,
+            preserve_ownership,
+            portable_permissions,
+            include_self,
+            dedup,
+            preserve_hardlinks,
+            report,
+            index,
+            on_duplicate,
+            entry_order,
+            temp_dir,
+            zstd_workers,
+            modified_since,
+            prune_empty_dirs,
+            text_eol,
+            min_file_size,
+            max_file_size,
+            preserve_absolute,
+            format,
+            read_concurrency,
+            preserve_xattrs,
+            use_mmap,
+            prefix,
+            exclude,
+            dedup_sources,
+            encrypt,
+            password,
+            password_env,
+            embed_metadata,
+            no_clobber,
+            write_manifest,
+            timestamp_rounding,
+            encryption_method,
+            single_arcname,
+            follow_symlinks,
+            write_checksum,
+            preserve_birthtime,
+            streaming_mode,
         } => {
+            let password = resolve_password(encrypt, password, password_env)?;
             println!("Zipping {:?} to {:?}...", input_paths, output_path);
-            zip_files(&output_path, &input_paths, compression)
+            let options = ZipOptions {
+                compression,
+                skip_hidden,
+                use_zipignore,
+                preserve_ownership,
+                portable_permissions,
+                exclude_self: !include_self,
+                dedup,
+                preserve_hardlinks,
+                report_path: report,
+                index_path: index,
+                on_duplicate,
+                entry_order,
+                temp_dir,
+                zstd_workers,
+                modified_since,
+                prune_empty_dirs,
+                text_eol,
+                min_file_size,
+                max_file_size,
+                preserve_absolute,
+                format,
+                read_concurrency,
+                preserve_xattrs,
+                use_mmap,
+                prefix,
+                exclude,
+                dedup_sources,
+                password,
+                embed_metadata,
+                overwrite: !no_clobber,
+                write_manifest,
+                timestamp_rounding,
+                encryption_method,
+                single_arcname,
+                follow_symlinks,
+                write_checksum,
+                preserve_birthtime,
+                streaming_mode,
+                ..Default::default()
+            };
+            zip_files_with_options(&output_path, &input_paths, &options)
                 .map_err(|e| format!("Failed to zip files: {}", e))?;
             println!("Successfully zipped files to {}.\n", output_path.display());
         }
+        Commands::List { zip_path, tree } => {
+            let entries =
+                list_archive(&zip_path).map_err(|e| format!("Failed to list archive: {}", e))?;
+            if tree {
+                let mut root = TreeNode::default();
+                for entry in &entries {
+                    root.insert(&entry.name);
+                }
+                print!("{}", root.render());
+            } else {
+                for entry in &entries {
+                    println!("{}", entry.name);
+                }
+            }
+        }
+        Commands::Info { zip_path } => {
+            let info = archive_info(&zip_path)
+                .map_err(|e| format!("Failed to read archive info: {}", e))?;
+            println!("Archive:           {}", zip_path.display());
+            println!("Entries:           {}", info.entry_count);
+            println!("Uncompressed size: {} bytes", info.total_size);
+            println!("Compressed size:   {} bytes", info.total_compressed_size);
+            println!("Ratio:             {:.1}%", info.ratio() * 100.0);
+            if info.prefix_len > 0 {
+                println!(
+                    "Prefix:            {} bytes (self-extracting stub)",
+                    info.prefix_len
+                );
+            }
+            if let Some(metadata) = &info.metadata {
+                println!(
+                    "Created by:        {} {} on {} at {} (unix time)",
+                    metadata.tool,
+                    metadata.version,
+                    metadata.host.as_deref().unwrap_or("an unknown host"),
+                    metadata.created
+                );
+            } else if info.comment.is_empty() {
+                println!("Comment:           (none)");
+            } else {
+                println!("Comment:           {}", info.comment);
+            }
+        }
         Commands::Unzip {
             zip_path,
             output_dir,
+            stdout,
+            entry,
+            preserve_ownership,
+            force_mode,
+            force_dir_mode,
+            report,
+            skip_identical,
+            strict_symlinks,
+            ledger,
+            continue_on_error,
+            auto_strip_root,
+            preserve_hardlinks,
+            retries,
+            mode_filter,
+            mode_mask,
+            max_entry_size,
+            max_entries,
+            on_duplicate,
+            tolerant_metadata,
+            preserve_birthtime,
+            preserve_special_bits,
+            oversized_entries,
+            preserve_atime,
+            write_concurrency,
+            preserve_xattrs,
+            backup,
+            order,
+            password,
+            password_env,
         } => {
+            if stdout {
+                let mut out = std::io::stdout().lock();
+                extract_single_entry_to(&zip_path, entry.as_deref(), &mut out)
+                    .map_err(|e| format!("Failed to extract entry: {}", e))?;
+                return Ok(());
+            }
+            let output_dir = output_dir.ok_or("--output-dir is required without --stdout")?;
             println!("Unzipping {:?} to {:?}...", zip_path, output_dir);
-            unzip_files(&zip_path, &output_dir)
+            // Only a warning: files that already exist, links and dedup entries need less
+            if let (Ok(needed), Ok(available)) = (
+                total_uncompressed_size(&zip_path),
+                available_space(&output_dir),
+            ) {
+                if needed > available {
+                    eprintln!(
+                        "Warning: the archive holds {} bytes but only {} are available in {}",
+                        needed,
+                        available,
+                        output_dir.display()
+                    );
+                }
+            }
+            let options = UnzipOptions {
+                preserve_ownership,
+                force_extract_mode: force_mode,
+                force_extract_dir_mode: force_dir_mode,
+                report_path: report,
+                skip_identical,
+                strict_symlinks,
+                ledger_path: ledger,
+                continue_on_error,
+                auto_strip_root,
+                preserve_hardlinks,
+                retries,
+                mode_filter,
+                mode_mask,
+                max_entry_size,
+                oversized_entries,
+                preserve_atime,
+                write_concurrency,
+                preserve_xattrs,
+                backup,
+                order,
+                password: resolve_password(false, password, password_env)?,
+                max_entries,
+                on_duplicate,
+                tolerant_metadata,
+                preserve_birthtime,
+                preserve_special_bits,
+                ..Default::default()
+            };
+            let failures = unzip_files_with_options(&zip_path, &output_dir, &options)
                 .map_err(|e| format!("Failed to unzip archive: {}", e))?;
+            if !failures.is_empty() {
+                for failure in &failures {
+                    eprintln!("Failed to extract {}: {}", failure.name, failure.error);
+                }
+                return Err(format!("{} entries could not be extracted", failures.len()).into());
+            }
             println!(
                 "Successfully unzipped archive {} to {}.\n",
                 zip_path.display(),
@@ -0,0 +1,197 @@
+// JSON summaries of archive operations, written when `report_path` is set on the zip or
+// unzip options, the sidecar entry index written by `zip_files` when `index_path` is set,
+// the provenance metadata it stores as the archive comment with `embed_metadata`, and the
+// manifest entry it appends with `write_manifest`.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zip::ZipArchive;
+
+use crate::unzip::{read_entries, ArchiveEntry};
+
+// Summary of a whole zip or unzip run. Timings cover the full operation; entries aren't
+// timed individually since they're read and written in parallel.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OperationReport {
+    pub operation: String,
+    pub archive: String,
+    pub elapsed_secs: f64,
+    pub total_entries: usize,
+    pub total_size: u64,
+    pub total_compressed_size: u64,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl OperationReport {
+    pub(crate) fn from_archive<R: Read + Seek>(
+        operation: &str,
+        archive_path: &Path,
+        archive: &mut ZipArchive<R>,
+        elapsed: Duration,
+    ) -> io::Result<Self> {
+        let entries = read_entries(archive)?;
+        Ok(OperationReport {
+            operation: operation.to_string(),
+            archive: archive_path.display().to_string(),
+            elapsed_secs: elapsed.as_secs_f64(),
+            total_entries: entries.len(),
+            total_size: entries.iter().map(|e| e.size).sum(),
+            total_compressed_size: entries.iter().map(|e| e.compressed_size).sum(),
+            entries,
+        })
+    }
+
+    pub(crate) fn write(&self, report_path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(report_path)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to write report '{}': {}",
+                report_path.display(),
+                e
+            ))
+        })?;
+        writer.flush()
+    }
+}
+
+// One line of the sidecar index written when `ZipOptions::index_path` is set
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub compressed_size: u64,
+    pub crc32: u32,
+}
+
+// Write one JSON object per archive entry, in central directory order, so the index can be
+// scanned line by line without opening the archive
+pub(crate) fn write_index<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index_path: &Path,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(index_path)?);
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        let line = IndexEntry {
+            name: entry.name().to_string(),
+            compressed_size: entry.compressed_size(),
+            crc32: entry.crc32(),
+        };
+        serde_json::to_writer(&mut writer, &line).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to write index '{}': {}",
+                index_path.display(),
+                e
+            ))
+        })?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+// Value of `ArchiveMetadata::tool` for archives written by this crate
+const METADATA_TOOL: &str = "ziprs";
+
+// Provenance of an archive, stored as its comment when `ZipOptions::embed_metadata` is set
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ArchiveMetadata {
+    pub tool: String,
+    pub version: String,
+    // Unix seconds at which the archive was written
+    pub created: u64,
+    // Name of the machine that wrote it, when known
+    pub host: Option<String>,
+}
+
+impl ArchiveMetadata {
+    pub(crate) fn current() -> Self {
+        ArchiveMetadata {
+            tool: METADATA_TOOL.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            host: hostname(),
+        }
+    }
+
+    pub(crate) fn to_comment(&self) -> String {
+        serde_json::to_string(self).expect("metadata serializes to JSON")
+    }
+
+    // Metadata held by an archive comment, or None for any other comment
+    pub(crate) fn from_comment(comment: &[u8]) -> Option<Self> {
+        serde_json::from_slice::<ArchiveMetadata>(comment)
+            .ok()
+            .filter(|metadata| metadata.tool == METADATA_TOOL)
+    }
+}
+
+fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for writes of its full length
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    let host = String::from_utf8_lossy(&buffer[..len]).into_owned();
+    (!host.is_empty()).then_some(host)
+}
+
+// Name of the entry holding the `ArchiveManifest`, always the last one in the archive
+pub const MANIFEST_NAME: &str = ".ziprs-manifest.json";
+
+// Recorded size and CRC-32 of one entry listed in an `ArchiveManifest`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+// Every other entry of an archive, stored in it as `MANIFEST_NAME` when
+// `ZipOptions::write_manifest` is set and checked by `verify_manifest`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ArchiveManifest {
+    pub entries: Vec<ManifestEntry>,
+    // Hex SHA-256 of the JSON encoding of `entries`, so a corrupted manifest shows up. It's
+    // unkeyed: anyone editing the manifest can recompute it, so it proves nothing about intent.
+    pub sha256: String,
+}
+
+impl ArchiveManifest {
+    pub(crate) fn from_archive<R: Read + Seek>(archive: &mut ZipArchive<R>) -> io::Result<Self> {
+        let mut entries = Vec::with_capacity(archive.len());
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i)?;
+            entries.push(ManifestEntry {
+                name: entry.name().to_string(),
+                size: entry.size(),
+                crc32: entry.crc32(),
+            });
+        }
+        let sha256 = entries_digest(&entries);
+        Ok(ArchiveManifest { entries, sha256 })
+    }
+
+    pub(crate) fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("manifest serializes to JSON")
+    }
+
+    // Whether `sha256` still matches the listed entries
+    pub(crate) fn is_intact(&self) -> bool {
+        entries_digest(&self.entries) == self.sha256
+    }
+}
+
+fn entries_digest(entries: &[ManifestEntry]) -> String {
+    let json = serde_json::to_vec(entries).expect("manifest entries serialize to JSON");
+    Sha256::digest(json)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
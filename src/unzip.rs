@@ -1,299 +1,5027 @@
+use clap::ValueEnum;
+use filetime::FileTime;
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
 use pyo3::exceptions::PyIOError;
 use pyo3::prelude::*;
+use pyo3::types::PyBytes;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs::{self};
-use std::io::{self, Read, Write};
-use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use zip::extra_fields::ExtraField;
+use zip::read::ZipFile;
+use zip::result::{ZipError, ZipResult};
 use zip::ZipArchive;
 
-// Core unzipping logic
-pub fn unzip_files(src_path: &Path, dst_path: &Path) -> io::Result<()> {
-    if !dst_path.exists() {
-        fs::create_dir_all(dst_path).map_err(|e| {
+use crate::central_directory::{
+    record_name, unsupported, write_u16, write_u32, CentralDirectory, CENTRAL_HEADER_LEN,
+};
+use crate::extra_fields::{
+    decode_birthtime, decode_link, decode_ownership, decode_xattrs, find_field, LinkKind,
+    UNIX_OWNERSHIP_ID, ZIPRS_BIRTHTIME_ID, ZIPRS_LINK_ID, ZIPRS_XATTR_ID,
+};
+use crate::report::{ArchiveManifest, ArchiveMetadata, OperationReport, MANIFEST_NAME};
+use crate::zip::{
+    compression_method_name, with_thread_pool, ContentTransform, SEGMENT_DIR_SUFFIX,
+    SEGMENT_MANIFEST_NAME,
+};
+
+// Default cap on the buffer pre-allocated for each entry before reading it
+pub const DEFAULT_READ_BUFFER_HINT: usize = 64 * 1024 * 1024;
+
+// Number of entries read from the archive that may wait for a writer thread during extraction
+pub const EXTRACT_QUEUE_LEN: usize = 16;
+
+// Options controlling how entries are extracted
+#[derive(Clone, Debug)]
+pub struct UnzipOptions {
+    // Restore the owner uid/gid recorded by `ZipOptions::preserve_ownership`.
+    // Only applied when running as root; silently skipped otherwise.
+    pub preserve_ownership: bool,
+    // Each entry's buffer is pre-sized to its uncompressed size, capped at this many bytes so
+    // a forged size can't trigger a huge allocation. 0 disables pre-sizing.
+    pub read_buffer_hint: usize,
+    // Mode applied to every extracted file instead of the archived `unix_mode`
+    pub force_extract_mode: Option<u32>,
+    // Mode applied to every extracted directory, once all files have been written
+    pub force_extract_dir_mode: Option<u32>,
+    // Rewrites each entry's content, keyed by its name in the archive, before it's written.
+    // Runs on the calling thread, which reads the archive, before the parallel write step.
+    pub transform: Option<ContentTransform>,
+    // Write a JSON summary of the extracted archive (entries, sizes, methods, timing) here
+    pub report_path: Option<PathBuf>,
+    // Don't rewrite files that already exist with the same size and CRC-32 as the entry
+    pub skip_identical: bool,
+    // Refuse symlink entries whose target points outside the destination instead of
+    // recreating them with a warning
+    pub strict_symlinks: bool,
+    // Record every extracted entry name in this file, and skip entries it already lists.
+    // Rerunning with the same ledger resumes an interrupted extraction.
+    pub ledger_path: Option<PathBuf>,
+    // Keep extracting when writing an entry fails, collecting the failures instead of
+    // aborting on the first one. Errors reading the archive itself still abort.
+    pub continue_on_error: bool,
+    // When every entry sits under one top-level directory, extract its contents straight
+    // into the destination instead of recreating that directory. Mixed roots are left as is.
+    pub auto_strip_root: bool,
+    // Recreate entries recorded by `ZipOptions::preserve_hardlinks` as hard links to their
+    // target instead of separate copies. Falls back to a copy when linking fails.
+    pub preserve_hardlinks: bool,
+    // Extra attempts at creating or writing a file after a transient `Interrupted` or
+    // `WouldBlock` error, with an increasing delay between them. 0 fails on the first error.
+    pub retries: usize,
+    // Only extract files and links whose unix mode satisfies `(mode & mode_mask) == mode_filter`,
+    // e.g. filter and mask 0o4000 for setuid files. The mask defaults to the filter and the
+    // filter to 0. Entries without a recorded mode are skipped while either is set.
+    pub mode_filter: Option<u32>,
+    pub mode_mask: Option<u32>,
+    // Largest uncompressed size (as recorded in the archive) of a single entry. Larger
+    // entries are refused before any of their content is read, per `oversized_entries`.
+    pub max_entry_size: Option<u64>,
+    pub oversized_entries: OversizePolicy,
+    // Set each extracted file's access time from the entry's extended timestamp field, or
+    // its modification time when the field holds no access time. The mtime itself is left
+    // as written. Filesystems mounted with `noatime` or `relatime` may not keep it.
+    pub preserve_atime: bool,
+    // Number of threads writing extracted files in parallel, e.g. fewer for a slow disk.
+    // Entries are always read from the archive one at a time. None uses rayon's global pool.
+    pub write_concurrency: Option<usize>,
+    // Restore the extended attributes recorded by `ZipOptions::preserve_xattrs`. Attributes
+    // the destination file system or the current user can't set are skipped with a warning.
+    pub preserve_xattrs: bool,
+    // Renames entries on extraction, called with each entry's name in the archive. Returning
+    // `Ok(None)` skips the entry. Mapped names are joined to the destination as returned
+    // (`auto_strip_root` doesn't apply to them) once checked not to escape it.
+    pub name_map: Option<NameMap>,
+    // Rename a file about to be overwritten by a file entry to `<name>.bak` first, replacing
+    // any backup left by an earlier run. Files kept by `skip_identical` aren't backed up.
+    pub backup: bool,
+    // Order in which entries are read from the archive and handed to the writer threads
+    pub order: ExtractOrder,
+    // Password decrypting encrypted entries, whether AES or ZipCrypto. Entries that aren't
+    // encrypted are read as usual; without it, any encrypted entry fails to extract.
+    pub password: Option<String>,
+    // Refuse archives with more entries than this before extracting anything, as the entry
+    // count comes from the central directory. Guards against archives of millions of tiny
+    // entries exhausting inodes. Zip archives only.
+    pub max_entries: Option<usize>,
+    // Which of several file entries sharing a name to extract, as in concatenated archives
+    pub on_duplicate: DuplicateEntries,
+    // Only log a warning when ownership, permissions or times can't be applied to an
+    // extracted path (e.g. on filesystems without unix permissions), keeping its content.
+    // Errors writing content still abort.
+    pub tolerant_metadata: bool,
+    // Set each extracted file's creation time from the field written by
+    // `ZipOptions::preserve_birthtime`. Only macOS can set a creation time; elsewhere the field
+    // is ignored, Linux in particular having no call to change it.
+    pub preserve_birthtime: bool,
+    // Keep the setuid, setgid and sticky bits of tar.gz entries. Off by default so an
+    // untrusted tarball can't plant setuid binaries; other permission bits are always kept.
+    pub preserve_special_bits: bool,
+}
+
+// Signature of a name map: `(archive_name) -> new name`
+pub type NameMapFn = dyn Fn(&str) -> io::Result<Option<String>> + Send + Sync;
+
+// Hook called as `name_map(archive_name)` for every entry, see `UnzipOptions::name_map`.
+// Always invoked from the thread reading the archive.
+#[derive(Clone)]
+pub struct NameMap(pub Arc<NameMapFn>);
+
+impl NameMap {
+    pub fn new(f: impl Fn(&str) -> io::Result<Option<String>> + Send + Sync + 'static) -> Self {
+        NameMap(Arc::new(f))
+    }
+
+    // Adapt a Python callable `name_map(archive_name) -> str | None`
+    pub(crate) fn from_py(callable: PyObject) -> Self {
+        NameMap::new(move |name| {
+            Python::with_gil(|py| {
+                callable
+                    .call1(py, (name,))
+                    .and_then(|result| result.extract::<Option<String>>(py))
+                    .map_err(|e| io::Error::other(format!("name_map failed for '{}': {}", name, e)))
+            })
+        })
+    }
+}
+
+impl fmt::Debug for NameMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NameMap")
+    }
+}
+
+// What to do with an entry larger than `UnzipOptions::max_entry_size`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OversizePolicy {
+    // Abort the extraction
+    #[default]
+    Error,
+    // Print a warning and extract the other entries
+    Skip,
+}
+
+// What `unzip_files` does with file entries sharing a name. Repeated directory entries are
+// always merged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DuplicateEntries {
+    // Extract the last one, overwriting the others
+    #[default]
+    Last,
+    // Extract the first one only
+    First,
+    // Refuse the archive before extracting anything
+    Error,
+    // Extract every one, the later ones as "name (1).ext", "name (2).ext", ...
+    Number,
+}
+
+// Order in which `unzip_files` extracts entries. Only the order of the writes changes, never
+// the extracted result.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExtractOrder {
+    // Central directory order
+    #[default]
+    #[value(name = "archive")]
+    Archive,
+    // Entries grouped by parent directory, each directory after its parent's, keeping the
+    // archive order within a directory. Improves write locality on spinning disks when the
+    // archive interleaves directories.
+    #[value(name = "by_dir")]
+    ByDir,
+}
+
+impl ExtractOrder {
+    // Indices of the archive's entries in extraction order
+    fn arrange<R: Read + Seek>(self, archive: &ZipArchive<R>) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..archive.len()).collect();
+        if self == ExtractOrder::ByDir {
+            let parent = |i: usize| {
+                let name = archive.name_for_index(i).unwrap_or_default();
+                name.trim_end_matches('/')
+                    .rsplit_once('/')
+                    .map_or("", |(parent, _)| parent)
+            };
+            // Stable, so entries of one directory keep their archive order
+            indices.sort_by_key(|&i| parent(i));
+        }
+        indices
+    }
+}
+
+impl Default for UnzipOptions {
+    fn default() -> Self {
+        UnzipOptions {
+            preserve_ownership: false,
+            read_buffer_hint: DEFAULT_READ_BUFFER_HINT,
+            force_extract_mode: None,
+            force_extract_dir_mode: None,
+            transform: None,
+            report_path: None,
+            skip_identical: false,
+            strict_symlinks: false,
+            ledger_path: None,
+            continue_on_error: false,
+            auto_strip_root: false,
+            preserve_hardlinks: false,
+            retries: 0,
+            mode_filter: None,
+            mode_mask: None,
+            max_entry_size: None,
+            oversized_entries: OversizePolicy::default(),
+            preserve_atime: false,
+            write_concurrency: None,
+            preserve_xattrs: false,
+            name_map: None,
+            backup: false,
+            order: ExtractOrder::default(),
+            password: None,
+            max_entries: None,
+            on_duplicate: DuplicateEntries::default(),
+            tolerant_metadata: false,
+            preserve_birthtime: false,
+            preserve_special_bits: false,
+        }
+    }
+}
+
+impl UnzipOptions {
+    // Entry `index` of `archive`, decrypted with `password` when one is set
+    fn entry_by_index<'a, R: Read + Seek>(
+        &self,
+        archive: &'a mut ZipArchive<R>,
+        index: usize,
+    ) -> ZipResult<ZipFile<'a, R>> {
+        match &self.password {
+            Some(password) => archive.by_index_decrypt(index, password.as_bytes()),
+            None => archive.by_index(index),
+        }
+    }
+
+    fn entry_by_name<'a, R: Read + Seek>(
+        &self,
+        archive: &'a mut ZipArchive<R>,
+        name: &str,
+    ) -> ZipResult<ZipFile<'a, R>> {
+        match &self.password {
+            Some(password) => archive.by_name_decrypt(name, password.as_bytes()),
+            None => archive.by_name(name),
+        }
+    }
+
+    // Whether an entry with this unix mode passes `mode_filter` / `mode_mask`
+    fn includes_mode(&self, mode: Option<u32>) -> bool {
+        if self.mode_filter.is_none() && self.mode_mask.is_none() {
+            return true;
+        }
+        let filter = self.mode_filter.unwrap_or(0);
+        let mask = self.mode_mask.unwrap_or(filter);
+        mode.is_some_and(|mode| mode & mask == filter)
+    }
+}
+
+// An entry that couldn't be extracted while `UnzipOptions::continue_on_error` is set
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtractFailure {
+    pub name: String,
+    pub error: String,
+}
+
+// A file entry read from the archive, ready to be written to disk
+struct ExtractEntry {
+    name: String,
+    path: PathBuf,
+    content: Vec<u8>,
+    mode: Option<u32>,
+    ownership: Option<(u32, u32)>,
+    // Set for entries whose content is stored in another entry (see `ZipOptions::dedup` and
+    // `ZipOptions::preserve_hardlinks`)
+    link: Option<(LinkKind, String)>,
+    // CRC-32 of `content` as recorded in the archive. None once the content no longer
+    // matches the stored entry (links, transforms).
+    crc32: Option<u32>,
+    // Symlink entry whose content is the link target
+    symlink: bool,
+    // Access time to apply (unix seconds), see `UnzipOptions::preserve_atime`
+    atime: Option<i64>,
+    // Extended attributes to set, see `UnzipOptions::preserve_xattrs`
+    xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+    // Creation time to apply (unix seconds and nanoseconds), see
+    // `UnzipOptions::preserve_birthtime`
+    birthtime: Option<(i64, u32)>,
+}
+
+// Relative path for a name produced by `UnzipOptions::name_map`, or None when it could
+// escape the destination (absolute, `..`)
+fn enclosed_path(name: &str) -> Option<PathBuf> {
+    if name.contains('\0') {
+        return None;
+    }
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+// Resolve `.` and `..` components without touching the filesystem
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// Returns true if the symlink at `link_path` pointing to `target` stays under `root`.
+// Checked lexically: links created earlier in the same extraction aren't followed.
+fn link_stays_inside(link_path: &Path, target: &Path, root: &Path) -> io::Result<bool> {
+    let root = normalize_lexically(&std::path::absolute(root)?);
+    let resolved = if target.is_absolute() {
+        normalize_lexically(target)
+    } else {
+        let link_dir = std::path::absolute(link_path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        normalize_lexically(&link_dir.join(target))
+    };
+    Ok(resolved.starts_with(&root))
+}
+
+// Recreate a symlink entry. Targets escaping `dst_path` are refused when `strict` is set,
+// and recreated verbatim with a warning otherwise.
+fn create_symlink(entry: &ExtractEntry, dst_path: &Path, strict: bool) -> io::Result<()> {
+    let target = std::str::from_utf8(&entry.content).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Symlink target of '{}' is not valid UTF-8", entry.name),
+        )
+    })?;
+
+    if !link_stays_inside(&entry.path, Path::new(target), dst_path)? {
+        if strict {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "Refusing to create symlink '{}' pointing outside the destination: '{}'",
+                    entry.name, target
+                ),
+            ));
+        }
+        warn!(
+            "Symlink '{}' points outside the destination: '{}'",
+            entry.name, target
+        );
+    }
+
+    prepare_link_path(&entry.path)?;
+    std::os::unix::fs::symlink(target, &entry.path).map_err(|e| {
+        io::Error::other(format!(
+            "Failed to create symlink '{}': {}",
+            entry.path.display(),
+            e
+        ))
+    })?;
+
+    if let Some((uid, gid)) = entry.ownership {
+        std::os::unix::fs::lchown(&entry.path, Some(uid), Some(gid))?;
+    }
+    Ok(())
+}
+
+// Create the parent directories of a link and remove any file already at its path, since
+// neither symlink() nor hard_link() replaces an existing file
+fn prepare_link_path(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(path),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// Recreate a hard link entry as another name for its extracted target. Writes the entry's
+// own copy of the content instead when there's no target on disk or linking fails, e.g.
+// because the two paths are on different devices.
+fn create_hardlink(
+    entry: &ExtractEntry,
+    target: Option<&Path>,
+    options: &UnzipOptions,
+) -> io::Result<()> {
+    if let Some(target) = target {
+        prepare_link_path(&entry.path)?;
+        if fs::hard_link(target, &entry.path).is_ok() {
+            return Ok(());
+        }
+    }
+    write_entry(entry, options)
+}
+
+// Returns true if the file at `path` already holds exactly `content`. The on-disk file is
+// only read and checksummed when its size matches.
+fn matches_on_disk(path: &Path, content: &[u8], crc32: Option<u32>) -> io::Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() && metadata.len() == content.len() as u64 => {}
+        Ok(_) => return Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    }
+
+    let expected = crc32.unwrap_or_else(|| crc32fast::hash(content));
+    let mut hasher = crc32fast::Hasher::new();
+    let mut file = fs::File::open(path)?;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize() == expected)
+}
+
+// Append-only list of the entries already extracted, one name per line. Each name goes out
+// in a single unbuffered append, so an interruption loses at most the entry being recorded.
+struct Ledger {
+    file: Mutex<fs::File>,
+}
+
+impl Ledger {
+    // Open (or create) the ledger, returning the names recorded by previous runs
+    fn open(path: &Path) -> io::Result<(Self, HashSet<String>)> {
+        let done = match fs::read_to_string(path) {
+            Ok(contents) => contents.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| {
+                io::Error::other(format!("Failed to open ledger '{}': {}", path.display(), e))
+            })?;
+        Ok((
+            Ledger {
+                file: Mutex::new(file),
+            },
+            done,
+        ))
+    }
+
+    fn record(&self, name: &str) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(format!("{}\n", name).as_bytes())
+    }
+}
+
+// Presents the volumes of a split archive (`name.z01`, `name.z02`, ..., `name.zip`) as a single
+// contiguous stream. A plain `.zip` without companion volumes is read as-is.
+//
+// Volumes are concatenated byte for byte, which matches archives cut into pieces after being
+// written (e.g. with `split`). PKWARE multi-disk archives store offsets relative to each disk
+// and are still rejected by the zip reader.
+//
+// The stream can end in a replacement for the volumes' bytes, see `replace_tail`.
+pub(crate) struct SpannedReader {
+    // Each volume with the offset of its first byte in the logical stream
+    volumes: Vec<(fs::File, u64)>,
+    // Where `tail` takes over from the volumes
+    tail_start: u64,
+    tail: Vec<u8>,
+    len: u64,
+    pos: u64,
+}
+
+impl SpannedReader {
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let mut volume_paths = Vec::new();
+        for number in 1.. {
+            let candidate = path.with_extension(format!("z{:02}", number));
+            if !candidate.is_file() {
+                break;
+            }
+            volume_paths.push(candidate);
+        }
+        volume_paths.push(path.to_path_buf());
+
+        let mut volumes = Vec::with_capacity(volume_paths.len());
+        let mut len = 0;
+        for volume_path in volume_paths {
+            let file = fs::File::open(&volume_path)?;
+            let volume_len = file.metadata()?.len();
+            volumes.push((file, len));
+            len += volume_len;
+        }
+        Ok(SpannedReader {
+            volumes,
+            tail_start: len,
+            tail: Vec::new(),
+            len,
+            pos: 0,
+        })
+    }
+
+    // Read `tail` in place of everything from `start` on, such as a rewritten central directory
+    fn replace_tail(&mut self, start: u64, tail: Vec<u8>) {
+        self.tail_start = start;
+        self.len = start + tail.len() as u64;
+        self.tail = tail;
+        self.pos = 0;
+    }
+}
+
+impl Read for SpannedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos >= self.tail_start {
+            let at = (self.pos - self.tail_start) as usize;
+            let read = buf.len().min(self.tail.len() - at);
+            buf[..read].copy_from_slice(&self.tail[at..at + read]);
+            self.pos += read as u64;
+            return Ok(read);
+        }
+        // Last volume starting at or before the current position
+        let index = self
+            .volumes
+            .partition_point(|(_, start)| *start <= self.pos)
+            - 1;
+        let volume_end = self
+            .volumes
+            .get(index + 1)
+            .map_or(self.tail_start, |(_, start)| (*start).min(self.tail_start));
+        let (file, start) = &mut self.volumes[index];
+        file.seek(SeekFrom::Start(self.pos - *start))?;
+        let max_len = buf.len().min((volume_end - self.pos) as usize);
+        let read = file.read(&mut buf[..max_len])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for SpannedReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+}
+
+fn running_as_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+// Days between 1970-01-01 and the given proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Access time recorded for an entry in unix seconds: the extended timestamp's access time,
+// else its modification time, else the MS-DOS modification time (which has no time zone
+// and is read as UTC)
+fn entry_atime<R: Read>(entry: &ZipFile<R>) -> Option<i64> {
+    let extended = entry.extra_data_fields().find_map(|field| match field {
+        ExtraField::ExtendedTimestamp(timestamp) => timestamp.ac_time().or(timestamp.mod_time()),
+        _ => None,
+    });
+    extended.map(i64::from).or_else(|| {
+        entry.last_modified().map(|dos| {
+            let days = days_from_civil(dos.year().into(), dos.month().into(), dos.day().into());
+            days * 86_400
+                + i64::from(dos.hour()) * 3_600
+                + i64::from(dos.minute()) * 60
+                + i64::from(dos.second())
+        })
+    })
+}
+
+fn apply_ownership(path: &Path, ownership: Option<(u32, u32)>) -> io::Result<()> {
+    if let Some((uid, gid)) = ownership {
+        std::os::unix::fs::chown(path, Some(uid), Some(gid)).map_err(|e| {
             io::Error::other(format!(
-                "Failed to create destination directory '{}': {}",
-                dst_path.display(),
+                "Failed to set ownership on '{}': {}",
+                path.display(),
                 e
             ))
         })?;
     }
+    Ok(())
+}
+
+// Set extended attributes on an extracted file. Failures (no xattr support, namespaces that
+// need privileges) only cost that attribute.
+fn apply_xattrs(path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) {
+    for (name, value) in xattrs {
+        let name = std::ffi::OsStr::from_bytes(name);
+        if let Err(e) = xattr::set(path, name, value) {
+            warn!(
+                "Failed to set extended attribute {} on {}: {}",
+                name.to_string_lossy(),
+                path.display(),
+                e
+            );
+        }
+    }
+}
 
-    let file = fs::File::open(src_path).map_err(|e| {
+// Open an archive for reading. Companion `.z01`, `.z02`, ... volumes are picked up
+// automatically for split archives.
+//
+// Data before the archive (a self-extracting stub) or after it is tolerated: the zip crate
+// searches the whole file backwards for the end of central directory record, skipping
+// candidates that don't parse, and works out how far entry offsets are shifted by the
+// prefix. What can't be recovered: archives whose central directory is damaged or missing.
+// Trailing data that itself holds a complete zip archive is opened instead of the real one.
+//
+// The central directory is sanity checked before the zip crate parses it, so a corrupt or
+// hostile archive is rejected with a specific error instead of driving huge allocations.
+fn open_archive(src_path: &Path) -> io::Result<ZipArchive<SpannedReader>> {
+    let mut file = SpannedReader::open(src_path).map_err(|e| {
         io::Error::new(
             io::ErrorKind::NotFound,
             format!("Failed to open zip file '{}': {}", src_path.display(), e),
         )
     })?;
+    check_archive_size(src_path, file.len)?;
+    check_central_directory(src_path, &mut file)?;
+
+    let archive = ZipArchive::new(file).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to read zip archive: {}", e),
+        )
+    })?;
+    check_entry_names(src_path, &archive)?;
+    Ok(archive)
+}
+
+// Size of the smallest possible zip archive: a lone end of central directory record
+const MIN_ARCHIVE_SIZE: u64 = 22;
+
+// Reject inputs too small to hold an archive, such as the empty file a failed download
+// leaves behind, with a clearer error than the zip crate gives for them
+fn check_archive_size(src_path: &Path, len: u64) -> io::Result<()> {
+    let reason = match len {
+        0 => "empty input".to_string(),
+        len if len < MIN_ARCHIVE_SIZE => format!("only {} bytes long", len),
+        _ => return Ok(()),
+    };
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("'{}' is not a zip file: {}", src_path.display(), reason),
+    ))
+}
+
+// Longest entry name accepted. Real paths stay under Linux's PATH_MAX of 4096 bytes.
+const MAX_ENTRY_NAME_LEN: usize = 4096;
+
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: &[u8] = b"PK\x05\x06";
+const ZIP64_LOCATOR_SIGNATURE: &[u8] = b"PK\x06\x07";
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: &[u8] = b"PK\x06\x06";
+
+// What the end of central directory record claims about the central directory
+struct DirectoryClaims {
+    entries: u64,
+    size: u64,
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+// Read the entry count and size of the central directory from the last end of central
+// directory record, or its zip64 counterpart. None when no record is found where expected,
+// leaving the zip crate to report the problem.
+fn read_directory_claims<R: Read + Seek>(
+    reader: &mut R,
+    len: u64,
+) -> io::Result<Option<DirectoryClaims>> {
+    // The record is followed by a comment of at most 64 KiB
+    let tail_len = len.min(MIN_ARCHIVE_SIZE + u64::from(u16::MAX));
+    let mut tail = vec![0u8; tail_len as usize];
+    reader.seek(SeekFrom::Start(len - tail_len))?;
+    reader.read_exact(&mut tail)?;
+    let Some(pos) = (0..=tail.len() - MIN_ARCHIVE_SIZE as usize)
+        .rev()
+        .find(|&pos| tail[pos..].starts_with(END_OF_CENTRAL_DIRECTORY_SIGNATURE))
+    else {
+        return Ok(None);
+    };
+    let record = &tail[pos..];
+    let entries = u16::from_le_bytes([record[10], record[11]]);
+    let size = u32::from_le_bytes(record[12..16].try_into().unwrap());
+    if entries != u16::MAX && size != u32::MAX {
+        return Ok(Some(DirectoryClaims {
+            entries: u64::from(entries),
+            size: u64::from(size),
+        }));
+    }
+
+    // Saturated fields defer to the zip64 record, found through the locator just before
+    let Some(locator) = pos.checked_sub(20).map(|start| &tail[start..pos]) else {
+        return Ok(None);
+    };
+    if !locator.starts_with(ZIP64_LOCATOR_SIGNATURE) {
+        return Ok(None);
+    }
+    let offset = le_u64(locator, 8);
+    if offset.checked_add(56).is_none_or(|end| end > len) {
+        return Ok(None);
+    }
+    let mut zip64_record = [0u8; 56];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut zip64_record)?;
+    if !zip64_record.starts_with(ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE) {
+        return Ok(None);
+    }
+    Ok(Some(DirectoryClaims {
+        entries: le_u64(&zip64_record, 32),
+        size: le_u64(&zip64_record, 40),
+    }))
+}
+
+// Reject a central directory that can't be what it claims: larger than the whole input, or
+// too small to hold the number of entries it declares
+fn check_central_directory(src_path: &Path, reader: &mut SpannedReader) -> io::Result<()> {
+    let len = reader.len;
+    let claims = read_directory_claims(reader, len)?;
+    reader.seek(SeekFrom::Start(0))?;
+    let reason = match claims {
+        Some(claims) if claims.size > len => {
+            format!("it claims {} bytes in a file of {} bytes", claims.size, len)
+        }
+        Some(claims) if claims.entries.saturating_mul(CENTRAL_HEADER_LEN as u64) > claims.size => {
+            format!(
+                "it claims {} entries in {} bytes",
+                claims.entries, claims.size
+            )
+        }
+        _ => return Ok(()),
+    };
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "'{}' has a malformed central directory: {}",
+            src_path.display(),
+            reason
+        ),
+    ))
+}
+
+// Reject entry names longer than any real path, which only a crafted archive would have
+fn check_entry_names<R: Read + Seek>(src_path: &Path, archive: &ZipArchive<R>) -> io::Result<()> {
+    match archive
+        .file_names()
+        .find(|name| name.len() > MAX_ENTRY_NAME_LEN)
+    {
+        Some(name) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' has an entry name of {} bytes, over the limit of {}",
+                src_path.display(),
+                name.len(),
+                MAX_ENTRY_NAME_LEN
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+// `name` numbered for `DuplicateEntries::Number`: " (n)" goes before the extension of the
+// last path component, e.g. "docs/report (1).txt"
+fn numbered_name(name: &[u8], n: usize) -> Vec<u8> {
+    let file_start = name.iter().rposition(|&b| b == b'/').map_or(0, |i| i + 1);
+    // A leading dot starts a hidden file's name, not an extension
+    let split = name[file_start..]
+        .iter()
+        .rposition(|&b| b == b'.')
+        .filter(|&i| i > 0)
+        .map_or(name.len(), |i| file_start + i);
+    [
+        &name[..split],
+        format!(" ({})", n).as_bytes(),
+        &name[split..],
+    ]
+    .concat()
+}
+
+// Apply `policy` to file entries of `archive` sharing a name. The zip crate keys entries by
+// name, so of such entries it only sees the last, which is what `DuplicateEntries::Last`
+// extracts. The others are found in the raw central directory. For `First` and `Number` the
+// archive is reopened over a rewritten central directory dropping or renaming the later
+// entries, with the entry data left where it is. Zip64 archives can't be rewritten.
+fn resolve_duplicates(
+    src_path: &Path,
+    archive: ZipArchive<SpannedReader>,
+    policy: DuplicateEntries,
+) -> io::Result<ZipArchive<SpannedReader>> {
+    if policy == DuplicateEntries::Last {
+        return Ok(archive);
+    }
+    let start = archive.central_directory_start();
+    let directory = CentralDirectory::read(&mut SpannedReader::open(src_path)?, start)?;
+    if directory.records.len() == archive.len() {
+        return Ok(archive);
+    }
+
+    let names: HashSet<&[u8]> = directory.records.iter().map(|r| record_name(r)).collect();
+    let mut seen: HashMap<&[u8], usize> = HashMap::new();
+    let mut numbered: HashSet<Vec<u8>> = HashSet::new();
+    let mut duplicates: BTreeSet<String> = BTreeSet::new();
+    let mut records: Vec<Vec<u8>> = Vec::with_capacity(directory.records.len());
+    for record in &directory.records {
+        let name = record_name(record);
+        let copies = seen.entry(name).or_insert(0);
+        *copies += 1;
+        if *copies == 1 {
+            records.push(record.clone());
+            continue;
+        }
+        if name.ends_with(b"/") {
+            continue;
+        }
+        duplicates.insert(String::from_utf8_lossy(name).into_owned());
+        if policy == DuplicateEntries::Number {
+            // Skipping numbers already taken by other entries
+            let new_name = (1..)
+                .map(|n| numbered_name(name, n))
+                .find(|candidate| {
+                    !names.contains(candidate.as_slice()) && !numbered.contains(candidate)
+                })
+                .expect("unbounded numbering");
+            let new_len = u16::try_from(new_name.len())
+                .map_err(|_| unsupported("Numbered entry name is too long"))?;
+            let mut renamed = record[..CENTRAL_HEADER_LEN].to_vec();
+            write_u16(&mut renamed, 28, new_len);
+            renamed.extend_from_slice(&new_name);
+            renamed.extend_from_slice(&record[CENTRAL_HEADER_LEN + name.len()..]);
+            records.push(renamed);
+            numbered.insert(new_name);
+        }
+    }
+    if duplicates.is_empty() {
+        return Ok(archive);
+    }
+    if policy == DuplicateEntries::Error {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' has several entries named {}",
+                src_path.display(),
+                duplicates.into_iter().collect::<Vec<_>>().join(", ")
+            ),
+        ));
+    }
+    if directory.is_zip64() {
+        return Err(unsupported(
+            "Entries sharing a name can only be told apart in archives under 4 GiB",
+        ));
+    }
 
-    let mut archive = ZipArchive::new(file).map_err(|e| {
+    // Only the entry counts and size change; the directory keeps its offset. There are no
+    // more records than before, so the count still fits.
+    let mut end_records = directory.end_records;
+    let entries = records.len() as u16;
+    let size: usize = records.iter().map(Vec::len).sum();
+    write_u16(&mut end_records, 8, entries);
+    write_u16(&mut end_records, 10, entries);
+    write_u32(
+        &mut end_records,
+        12,
+        u32::try_from(size).map_err(|_| unsupported("Central directory is too large"))?,
+    );
+    let mut tail = records.concat();
+    tail.extend_from_slice(&end_records);
+
+    let mut reader = archive.into_inner();
+    reader.replace_tail(start, tail);
+    let archive = ZipArchive::new(reader).map_err(|e| {
         io::Error::new(
             io::ErrorKind::InvalidData,
             format!("Failed to read zip archive: {}", e),
         )
     })?;
+    check_entry_names(src_path, &archive)?;
+    Ok(archive)
+}
+
+// Delay before the given retry (1-based): 10ms, doubling each time up to 640ms
+fn retry_backoff(attempt: usize) -> Duration {
+    Duration::from_millis(10 << (attempt - 1).min(6))
+}
+
+// Run `op`, running it again up to `retries` times while it fails with a transient error
+fn with_retries<T>(retries: usize, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e)
+                if attempt < retries
+                    && matches!(
+                        e.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                    ) =>
+            {
+                attempt += 1;
+                std::thread::sleep(retry_backoff(attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+// `write_all` that also retries `WouldBlock` up to `retries` times. Like `write_all`, it
+// always retries `Interrupted`, and never rewrites bytes a partial write already accepted.
+fn write_all_with_retries<W: Write>(
+    writer: &mut W,
+    mut content: &[u8],
+    retries: usize,
+) -> io::Result<()> {
+    let mut attempt = 0;
+    while !content.is_empty() {
+        match writer.write(content) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => content = &content[written..],
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && attempt < retries => {
+                attempt += 1;
+                std::thread::sleep(retry_backoff(attempt));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Move whatever is at `path`, other than a directory, to `<path>.bak`, replacing an older
+// backup. Nothing to do when the path doesn't exist yet.
+fn backup_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".bak");
+            fs::rename(path, &backup).map_err(|e| {
+                io::Error::other(format!(
+                    "Failed to back up '{}' to '{}': {}",
+                    path.display(),
+                    Path::new(&backup).display(),
+                    e
+                ))
+            })
+        }
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// Create or truncate the output file at `path`. A new file is created with the permission
+// bits of `mode` from the start, so there's no moment where it's open to more users than its
+// final mode allows. The umask may narrow them, and special bits are left to the chmod that
+// follows (chown clears them), so the mode is still applied once the file is written.
+fn create_output_file(path: &Path, mode: Option<u32>) -> io::Result<fs::File> {
+    let mut open_options = fs::OpenOptions::new();
+    open_options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        open_options.mode(mode & 0o777);
+    }
+    open_options.open(path)
+}
+
+// Write one regular file entry to disk, then apply its ownership and permissions
+fn write_entry(entry: &ExtractEntry, options: &UnzipOptions) -> io::Result<()> {
+    let ExtractEntry {
+        path,
+        content,
+        crc32,
+        ..
+    } = entry;
+
+    // Ensure parent directory exists before writing the file.
+    // This is necessary because a file might be listed in the zip archive
+    // before its parent directory, or the directory creation pass might have missed it
+    // if it wasn't explicitly listed as a directory entry in the zip.
+    if let Some(p) = path.parent() {
+        if !p.exists() {
+            fs::create_dir_all(p).map_err(|e| {
+                io::Error::other(format!(
+                    "Failed to create parent directory for file '{}': {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
+    }
+
+    // Leave files that already hold the right content untouched, keeping their mtime
+    let unchanged = options.skip_identical && matches_on_disk(path, content, *crc32)?;
+    if !unchanged {
+        if options.backup {
+            backup_existing(path)?;
+        }
+        let mode = options.force_extract_mode.or(entry.mode);
+        let mut outfile = with_retries(options.retries, || create_output_file(path, mode))
+            .map_err(|e| {
+                io::Error::other(format!(
+                    "Failed to create output file '{}': {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        write_all_with_retries(&mut outfile, content, options.retries).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to write content to file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+
+    tolerate_metadata_errors(options, apply_file_metadata(entry, options))
+}
+
+// Apply the ownership, mode, extended attributes and access time of `entry` to the file
+// just written for it
+fn apply_file_metadata(entry: &ExtractEntry, options: &UnzipOptions) -> io::Result<()> {
+    let path = &entry.path;
+    // Ownership goes first since chown may clear setuid/setgid bits
+    apply_ownership(path, entry.ownership)?;
+
+    // Set permissions if available, unless a forced mode overrides the archived one
+    #[cfg(unix)]
+    if let Some(mode) = options.force_extract_mode.or(entry.mode) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to set permissions on '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+
+    apply_xattrs(path, &entry.xattrs);
+
+    if let Some(atime) = entry.atime {
+        filetime::set_file_atime(path, FileTime::from_unix_time(atime, 0)).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to set access time on '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+
+    if let Some(birthtime) = entry.birthtime {
+        set_birthtime(path, birthtime).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to set creation time on '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+// Set the creation time of `path` to `(seconds, nanoseconds)` since the epoch
+#[cfg(target_os = "macos")]
+fn set_birthtime(path: &Path, (seconds, nanos): (i64, u32)) -> io::Result<()> {
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: attrlist is plain data, valid when zeroed
+    let mut attributes: libc::attrlist = unsafe { std::mem::zeroed() };
+    attributes.bitmapcount = libc::ATTR_BIT_MAP_COUNT;
+    attributes.commonattr = libc::ATTR_CMN_CRTIME;
+    let mut created = libc::timespec {
+        tv_sec: seconds,
+        tv_nsec: nanos.into(),
+    };
+    // SAFETY: the buffer holds exactly the one timespec the attribute list asks for
+    let result = unsafe {
+        libc::setattrlist(
+            c_path.as_ptr(),
+            (&mut attributes as *mut libc::attrlist).cast(),
+            (&mut created as *mut libc::timespec).cast(),
+            std::mem::size_of::<libc::timespec>(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn set_birthtime(path: &Path, _birthtime: (i64, u32)) -> io::Result<()> {
+    debug!(
+        "Not restoring the creation time of {}: unsupported on this platform",
+        path.display()
+    );
+    Ok(())
+}
+
+// Pass on `result`, the outcome of applying metadata to an extracted path, unless
+// `tolerant_metadata` is set: then a failure is only logged
+fn tolerate_metadata_errors(options: &UnzipOptions, result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if options.tolerant_metadata => {
+            warn!("{}; keeping the extracted content", e);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// First bytes of every gzip stream, used to tell `ArchiveFormat::TarGz` archives apart
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn is_gzip(src_path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 2];
+    match fs::File::open(src_path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == GZIP_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Names of the options set in `options` that tar.gz extraction doesn't honor. Tar entries are
+// unpacked by the `tar` crate rather than `stream_entries`, so none of the per-entry checks,
+// hooks or reports run for them.
+fn tar_unsupported_options(options: &UnzipOptions) -> Vec<&'static str> {
+    [
+        ("preserve_ownership", options.preserve_ownership),
+        ("force_extract_mode", options.force_extract_mode.is_some()),
+        (
+            "force_extract_dir_mode",
+            options.force_extract_dir_mode.is_some(),
+        ),
+        ("transform", options.transform.is_some()),
+        ("report_path", options.report_path.is_some()),
+        ("skip_identical", options.skip_identical),
+        ("strict_symlinks", options.strict_symlinks),
+        ("ledger_path", options.ledger_path.is_some()),
+        ("continue_on_error", options.continue_on_error),
+        ("auto_strip_root", options.auto_strip_root),
+        ("preserve_hardlinks", options.preserve_hardlinks),
+        ("mode_filter", options.mode_filter.is_some()),
+        ("mode_mask", options.mode_mask.is_some()),
+        ("max_entry_size", options.max_entry_size.is_some()),
+        ("preserve_atime", options.preserve_atime),
+        ("preserve_xattrs", options.preserve_xattrs),
+        ("name_map", options.name_map.is_some()),
+        ("backup", options.backup),
+        ("password", options.password.is_some()),
+        ("max_entries", options.max_entries.is_some()),
+        (
+            "on_duplicate",
+            options.on_duplicate != DuplicateEntries::default(),
+        ),
+        ("preserve_birthtime", options.preserve_birthtime),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect()
+}
+
+// Unpack a gzip-compressed tar archive, restoring permissions and mtimes. Entries that would
+// land outside `dst_path` are skipped by the tar crate. Options it doesn't honor (see
+// `tar_unsupported_options`) are refused rather than silently ignored.
+fn extract_tar_gz(src_path: &Path, dst_path: &Path, options: &UnzipOptions) -> io::Result<()> {
+    let unsupported = tar_unsupported_options(options);
+    if !unsupported.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Options not supported for tar.gz archives: {}",
+                unsupported.join(", ")
+            ),
+        ));
+    }
+    let file = io::BufReader::new(fs::File::open(src_path)?);
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    archive.set_preserve_permissions(true);
+    if !options.preserve_special_bits {
+        archive.set_mask(0o7000);
+    }
+    archive.set_overwrite(true);
+    archive.unpack(dst_path)
+}
+
+// Entries handled by `stream_entries` other than the regular files sent to the writers
+#[derive(Default)]
+struct StreamedEntries {
+    // Directories created, with the ownership applied to them
+    dirs: Vec<(PathBuf, Option<(u32, u32)>)>,
+    // Output paths of the entries skipped because the ledger lists them
+    skipped: HashMap<String, PathBuf>,
+    // Output paths of the regular files sent to the writers
+    written: HashMap<String, PathBuf>,
+    // Names of the entries links may point to: every file read, even if the transform
+    // dropped it afterwards
+    link_targets: HashSet<String>,
+    // Link and symlink entries, written once the regular files are on disk
+    deferred: Vec<ExtractEntry>,
+}
+
+// Read every entry of the archive in order, creating directories right away and sending
+// regular files to `sender` for the writer threads. Runs on the calling thread, which is
+// the only one reading the archive and calling the Python hooks.
+fn stream_entries<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    dst_path: &Path,
+    options: &UnzipOptions,
+    strip_root: Option<&Path>,
+    already_extracted: &HashSet<String>,
+    sender: mpsc::SyncSender<ExtractEntry>,
+) -> io::Result<StreamedEntries> {
+    let mut streamed = StreamedEntries::default();
+    // Changing ownership requires privileges, so only bother decoding it when running as root
+    let restore_ownership = options.preserve_ownership && running_as_root();
+
+    for i in options.order.arrange(archive) {
+        // Get the file entry from the zip archive.
+        let mut file_in_zip = options.entry_by_index(archive, i).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to read file in zip by index {}: {}", i, e),
+            )
+        })?;
+
+        // Get the path of the file in the zip archive.
+        let outpath = if let Some(name_map) = &options.name_map {
+            let Some(mapped) = (name_map.0)(file_in_zip.name())? else {
+                debug!("Skipping {} (name map)", file_in_zip.name());
+                continue;
+            };
+            match enclosed_path(&mapped) {
+                // Like a stripped root, an empty name maps onto the destination itself
+                Some(path) if path.as_os_str().is_empty() => continue,
+                Some(path) => dst_path.join(path),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "name_map mapped '{}' to '{}', which escapes the destination",
+                            file_in_zip.name(),
+                            mapped
+                        ),
+                    ))
+                }
+            }
+        } else {
+            match (file_in_zip.enclosed_name(), strip_root) {
+                (Some(path), Some(root)) => match path.strip_prefix(root) {
+                    // The stripped root itself maps onto the destination, which already exists
+                    Ok(rest) if rest.as_os_str().is_empty() => continue,
+                    Ok(rest) => dst_path.join(rest),
+                    Err(_) => continue,
+                },
+                (Some(path), None) => dst_path.join(path),
+                (None, _) => continue,
+            }
+        };
+
+        let ownership = if restore_ownership {
+            file_in_zip
+                .extra_data()
+                .and_then(|extra| find_field(extra, UNIX_OWNERSHIP_ID))
+                .and_then(decode_ownership)
+        } else {
+            None
+        };
+
+        // If the file is a directory, add it to the list of directories to create.
+        // Directories are created as they come, with writer threads creating any parent
+        // a file needs before its directory entry shows up
+        if file_in_zip.name().ends_with('/') {
+            fs::create_dir_all(&outpath).map_err(|e| {
+                io::Error::other(format!(
+                    "Failed to create directory structure at '{}': {}",
+                    outpath.display(),
+                    e
+                ))
+            })?;
+            tolerate_metadata_errors(options, apply_ownership(&outpath, ownership))?;
+            streamed.dirs.push((outpath, ownership));
+        } else if !options.includes_mode(file_in_zip.unix_mode()) {
+            debug!("Skipping {} (mode filter)", file_in_zip.name());
+        } else if let Some(limit) = options
+            .max_entry_size
+            .filter(|&limit| file_in_zip.size() > limit)
+        {
+            let message = format!(
+                "Entry '{}' is {} bytes, over the {} byte limit",
+                file_in_zip.name(),
+                file_in_zip.size(),
+                limit
+            );
+            match options.oversized_entries {
+                OversizePolicy::Error => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message))
+                }
+                OversizePolicy::Skip => warn!("{}; skipping it", message),
+            }
+        } else if already_extracted.contains(file_in_zip.name()) {
+            debug!("Skipping {} (already in the ledger)", file_in_zip.name());
+            streamed
+                .skipped
+                .insert(file_in_zip.name().to_string(), outpath);
+        } else {
+            // Pre-size the buffer to avoid repeated reallocations while reading
+            let capacity = file_in_zip.size().min(options.read_buffer_hint as u64) as usize;
+            let mut content = Vec::with_capacity(capacity);
+            file_in_zip.read_to_end(&mut content).map_err(|e| {
+                io::Error::other(format!(
+                    "Failed to read file content from zip entry '{}': {}",
+                    file_in_zip.name(),
+                    e
+                ))
+            })?;
+            let link = file_in_zip
+                .extra_data()
+                .and_then(|extra| find_field(extra, ZIPRS_LINK_ID))
+                .and_then(decode_link);
+            let mut entry = ExtractEntry {
+                name: file_in_zip.name().to_string(),
+                path: outpath,
+                content,
+                mode: file_in_zip.unix_mode(),
+                ownership,
+                crc32: link.is_none().then(|| file_in_zip.crc32()),
+                link,
+                symlink: file_in_zip.is_symlink(),
+                atime: options
+                    .preserve_atime
+                    .then(|| entry_atime(&file_in_zip))
+                    .flatten(),
+                xattrs: if options.preserve_xattrs {
+                    file_in_zip
+                        .extra_data()
+                        .and_then(|extra| find_field(extra, ZIPRS_XATTR_ID))
+                        .and_then(decode_xattrs)
+                        .unwrap_or_default()
+                } else {
+                    Vec::new()
+                },
+                birthtime: options
+                    .preserve_birthtime
+                    .then(|| {
+                        file_in_zip
+                            .extra_data()
+                            .and_then(|extra| find_field(extra, ZIPRS_BIRTHTIME_ID))
+                            .and_then(decode_birthtime)
+                    })
+                    .flatten(),
+            };
+            drop(file_in_zip);
+
+            // Links get their content once every file is written, see `write_links`
+            if entry.link.is_some() {
+                streamed.deferred.push(entry);
+                continue;
+            }
+            streamed.link_targets.insert(entry.name.clone());
+            if let Some(transform) = &options.transform {
+                let content = std::mem::take(&mut entry.content);
+                match (transform.0)(&entry.name, content)? {
+                    Some(content) => {
+                        entry.content = content;
+                        entry.crc32 = None;
+                    }
+                    None => continue,
+                }
+            }
+            // Symlinks are created only after every regular file has been written, so a
+            // link can't redirect a file write outside the destination
+            if entry.symlink {
+                streamed.deferred.push(entry);
+                continue;
+            }
+            streamed
+                .written
+                .insert(entry.name.clone(), entry.path.clone());
+            // Only fails once the writers stopped on an error, which is reported instead
+            sender
+                .send(entry)
+                .map_err(|_| io::Error::other("Extraction stopped"))?;
+        }
+    }
+    Ok(streamed)
+}
+
+// Core unzipping logic
+pub fn unzip_files(src_path: &Path, dst_path: &Path) -> io::Result<()> {
+    unzip_files_with_options(src_path, dst_path, &UnzipOptions::default())?;
+    Ok(())
+}
+
+// Same as `unzip_files`, with full control over the extraction options.
+// Returns the entries skipped because of `continue_on_error`, empty otherwise.
+pub fn unzip_files_with_options(
+    src_path: &Path,
+    dst_path: &Path,
+    options: &UnzipOptions,
+) -> io::Result<Vec<ExtractFailure>> {
+    let started = Instant::now();
+    if !dst_path.exists() {
+        fs::create_dir_all(dst_path).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to create destination directory '{}': {}",
+                dst_path.display(),
+                e
+            ))
+        })?;
+    }
+
+    if is_gzip(src_path)? {
+        extract_tar_gz(src_path, dst_path, options)?;
+        info!(
+            "Extracted {} to {} in {:.2?}",
+            src_path.display(),
+            dst_path.display(),
+            started.elapsed()
+        );
+        return Ok(Vec::new());
+    }
+
+    let mut archive = resolve_duplicates(src_path, open_archive(src_path)?, options.on_duplicate)?;
+    if let Some(max_entries) = options.max_entries {
+        if archive.len() > max_entries {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "'{}' holds {} entries, over the limit of {}",
+                    src_path.display(),
+                    archive.len(),
+                    max_entries
+                ),
+            ));
+        }
+    }
+    let strip_root = if options.auto_strip_root {
+        single_root(&mut archive)?
+    } else {
+        None
+    };
+    let (ledger, already_extracted) = match &options.ledger_path {
+        Some(ledger_path) => {
+            let (ledger, done) = Ledger::open(ledger_path)?;
+            (Some(ledger), done)
+        }
+        None => (None, HashSet::new()),
+    };
+
+    // Entries that failed while `continue_on_error` is set
+    let failures: Mutex<Vec<ExtractFailure>> = Mutex::new(Vec::new());
+    let record_failure = |entry: &ExtractEntry, error: io::Error| {
+        failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(ExtractFailure {
+                name: entry.name.clone(),
+                error: error.to_string(),
+            });
+    };
+    let record_success = |entry: &ExtractEntry| -> io::Result<()> {
+        debug!("Extracted {}", entry.name);
+        if let Some(ledger) = &ledger {
+            ledger.record(&entry.name)?;
+        }
+        Ok(())
+    };
+    let record = |entry: &ExtractEntry, result: io::Result<()>| -> io::Result<()> {
+        match result {
+            Ok(()) => record_success(entry),
+            Err(e) if options.continue_on_error => {
+                record_failure(entry, e);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    };
+
+    // Files are read one at a time on this thread and written in parallel by up to
+    // `write_concurrency` threads as they arrive. The queue between them holds at most
+    // `EXTRACT_QUEUE_LEN` entries, so only that many plus one per writer thread are in
+    // memory at once, however large the archive.
+    let (sender, receiver) = mpsc::sync_channel::<ExtractEntry>(EXTRACT_QUEUE_LEN);
+    let (streamed, written) = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || {
+            with_thread_pool(options.write_concurrency, || {
+                receiver
+                    .into_iter()
+                    .par_bridge()
+                    .try_for_each(|entry| record(&entry, write_entry(&entry, options)))
+            })
+        });
+        let streamed = stream_entries(
+            &mut archive,
+            dst_path,
+            options,
+            strip_root.as_deref(),
+            &already_extracted,
+            sender,
+        );
+        let written = writer
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (streamed, written)
+    });
+    // A failed write stops the reader too, so its error comes first
+    written??;
+    let StreamedEntries {
+        dirs: dirs_to_create,
+        skipped,
+        written,
+        link_targets,
+        deferred,
+    } = streamed?;
+
+    let (symlinks, links): (Vec<_>, Vec<_>) = deferred.into_iter().partition(|entry| entry.symlink);
+    // Every path written as a regular file, for `force_extract_dir_mode`
+    let mut file_paths: Vec<PathBuf> = written.values().cloned().collect();
+    for mut entry in links {
+        let Some((kind, target)) = entry.link.clone() else {
+            continue;
+        };
+        // Read the target back from the archive, so links see the content it had before the
+        // transform, or from disk when an earlier, resumed run extracted it
+        if link_targets.contains(&target) {
+            let mut target_entry = options.entry_by_name(&mut archive, &target)?;
+            target_entry.read_to_end(&mut entry.content)?;
+            entry.crc32 = Some(target_entry.crc32());
+        } else if let Some(target_path) = skipped.get(&target) {
+            entry.content = fs::read(target_path)?;
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Entry '{}' links to missing entry '{}'", entry.name, target),
+            ));
+        }
+        if let Some(transform) = &options.transform {
+            let content = std::mem::take(&mut entry.content);
+            match (transform.0)(&entry.name, content)? {
+                Some(content) => {
+                    entry.content = content;
+                    entry.crc32 = None;
+                }
+                None => continue,
+            }
+        }
+
+        // Hard links need their target on disk, so they're only created now
+        let result = if options.preserve_hardlinks && kind == LinkKind::Hardlink {
+            let target_path = written.get(&target).or_else(|| skipped.get(&target));
+            create_hardlink(&entry, target_path.map(PathBuf::as_path), options)
+        } else {
+            file_paths.push(entry.path.clone());
+            write_entry(&entry, options)
+        };
+        record(&entry, result)?;
+    }
+
+    for entry in &symlinks {
+        record(
+            entry,
+            create_symlink(entry, dst_path, options.strict_symlinks),
+        )?;
+    }
+
+    if let Some(dir_mode) = options.force_extract_dir_mode {
+        // Directories implied by file paths count too. Applied deepest first so a mode
+        // without search permission doesn't lock us out of the remaining subdirectories.
+        let mut dirs: Vec<&Path> = dirs_to_create.iter().map(|(p, _)| p.as_path()).collect();
+        dirs.extend(file_paths.iter().filter_map(|path| path.parent()));
+        dirs.retain(|p| p.starts_with(dst_path) && *p != dst_path);
+        dirs.sort_unstable_by(|a, b| b.cmp(a));
+        dirs.dedup();
+        for dir in dirs {
+            let result =
+                fs::set_permissions(dir, fs::Permissions::from_mode(dir_mode)).map_err(|e| {
+                    io::Error::other(format!(
+                        "Failed to set permissions on '{}': {}",
+                        dir.display(),
+                        e
+                    ))
+                });
+            tolerate_metadata_errors(options, result)?;
+        }
+    }
+
+    info!(
+        "Extracted {} to {} in {:.2?}",
+        src_path.display(),
+        dst_path.display(),
+        started.elapsed()
+    );
+    if let Some(report_path) = &options.report_path {
+        OperationReport::from_archive("unzip", src_path, &mut archive, started.elapsed())?
+            .write(report_path)?;
+    }
+
+    Ok(failures.into_inner().unwrap_or_else(|e| e.into_inner()))
+}
+
+// Name of the directory `unzip_many` extracts `src_path` into: its file name without the
+// archive extension, so both "logs.zip" and "logs.tar.gz" become "logs"
+fn archive_stem(src_path: &Path) -> io::Result<String> {
+    let stem = src_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' has no usable file name", src_path.display()),
+            )
+        })?;
+    Ok(stem.strip_suffix(".tar").unwrap_or(stem).to_string())
+}
+
+// Extract each archive in `src_paths` under `dst_path`. With `namespace` set each one gets a
+// subdirectory named after it, `dst_path/<archive_stem>/`, so their contents can't collide;
+// archives sharing a stem are refused before anything is extracted. Returns the directory
+// each archive was extracted into, in order.
+pub fn unzip_many(
+    src_paths: &[PathBuf],
+    dst_path: &Path,
+    namespace: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut dst_paths = Vec::with_capacity(src_paths.len());
+    for src_path in src_paths {
+        if !namespace {
+            dst_paths.push(dst_path.to_path_buf());
+            continue;
+        }
+        let path = dst_path.join(archive_stem(src_path)?);
+        if let Some(index) = dst_paths.iter().position(|other| *other == path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "'{}' and '{}' would both extract into '{}'",
+                    src_paths[index].display(),
+                    src_path.display(),
+                    path.display()
+                ),
+            ));
+        }
+        dst_paths.push(path);
+    }
+
+    let options = UnzipOptions::default();
+    for (src_path, path) in src_paths.iter().zip(&dst_paths) {
+        unzip_files_with_options(src_path, path, &options)?;
+    }
+    Ok(dst_paths)
+}
+
+#[pyfunction]
+#[pyo3(name = "unzip_many", signature = (srcs, dst_py, namespace = false))]
+pub fn unzip_many_pywrapper(
+    srcs: Vec<String>,
+    dst_py: String,
+    namespace: bool,
+) -> PyResult<Vec<String>> {
+    let src_paths: Vec<PathBuf> = srcs.iter().map(PathBuf::from).collect();
+    let dst_paths = unzip_many(&src_paths, Path::new(&dst_py), namespace)
+        .map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(dst_paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+// Extract only the entries listed in `names` under `dst_path`, creating their parent
+// directories and applying their recorded modes. Returns the names found, in the given order.
+// Missing names are skipped with a warning, or fail the whole call when `strict` is set
+// (before anything is written). Dedup links get the content of their target.
+pub fn extract_entries(
+    src_path: &Path,
+    names: &[String],
+    dst_path: &Path,
+    strict: bool,
+) -> io::Result<Vec<String>> {
+    let mut archive = open_archive(src_path)?;
+    let (found, missing): (Vec<&String>, Vec<&String>) = names
+        .iter()
+        .partition(|name| archive.index_for_name(name).is_some());
+    if !missing.is_empty() {
+        let missing: Vec<&str> = missing.iter().map(|name| name.as_str()).collect();
+        if strict {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Entries not found in '{}': {}",
+                    src_path.display(),
+                    missing.join(", ")
+                ),
+            ));
+        }
+        warn!(
+            "Entries not found in {}: {}",
+            src_path.display(),
+            missing.join(", ")
+        );
+    }
+
+    let options = UnzipOptions::default();
+    for name in &found {
+        let mut entry = archive.by_name(name)?;
+        let Some(path) = entry.enclosed_name().map(|path| dst_path.join(path)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Entry '{}' would extract outside the destination", name),
+            ));
+        };
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        let link = entry
+            .extra_data()
+            .and_then(|extra| find_field(extra, ZIPRS_LINK_ID))
+            .and_then(decode_link);
+        let mut extract = ExtractEntry {
+            name: name.to_string(),
+            path,
+            content,
+            mode: entry.unix_mode(),
+            ownership: None,
+            crc32: Some(entry.crc32()),
+            link: None,
+            symlink: entry.is_symlink(),
+            atime: None,
+            xattrs: Vec::new(),
+            birthtime: None,
+        };
+        drop(entry);
+        if let Some((_, target)) = link {
+            extract.content.clear();
+            archive
+                .by_name(&target)?
+                .read_to_end(&mut extract.content)?;
+            extract.crc32 = None;
+        }
+        if extract.symlink {
+            create_symlink(&extract, dst_path, false)?;
+        } else {
+            write_entry(&extract, &options)?;
+        }
+    }
+    Ok(found.into_iter().cloned().collect())
+}
+
+#[pyfunction]
+#[pyo3(name = "extract_entries", signature = (src_py, names, dst_py, strict = false))]
+pub fn extract_entries_pywrapper(
+    src_py: String,
+    names: Vec<String>,
+    dst_py: String,
+    strict: bool,
+) -> PyResult<Vec<String>> {
+    extract_entries(Path::new(&src_py), &names, Path::new(&dst_py), strict)
+        .map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Comment stored with entry `name`, empty when it has none
+pub fn read_entry_comment(src_path: &Path, name: &str) -> io::Result<String> {
+    let mut archive = open_archive(src_path)?;
+    let entry = archive.by_name(name).map_err(|e| match e {
+        ZipError::FileNotFound => io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No entry named '{}' in '{}'", name, src_path.display()),
+        ),
+        e => e.into(),
+    })?;
+    Ok(entry.comment().to_string())
+}
+
+#[pyfunction]
+#[pyo3(name = "read_entry_comment")]
+pub fn read_entry_comment_pywrapper(src_py: String, name: String) -> PyResult<String> {
+    read_entry_comment(Path::new(&src_py), &name).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Check every entry of the archive at `src_path` against the manifest written by
+// `ZipOptions::write_manifest`, returning the sorted names of entries added, removed or
+// changed since: their recorded size or CRC-32 differs from the manifest, or their content
+// no longer matches the recorded CRC-32 (not checked for encrypted entries). A missing
+// manifest, or one whose own digest doesn't match, is an error. This catches corruption and
+// careless edits, not deliberate tampering, since the manifest can be rewritten as well.
+pub fn verify_manifest(src_path: &Path) -> io::Result<Vec<String>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut archive = open_archive(src_path)?;
+    let manifest: ArchiveManifest = {
+        let mut entry = archive.by_name(MANIFEST_NAME).map_err(|e| match e {
+            ZipError::FileNotFound => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' has no {} entry", src_path.display(), MANIFEST_NAME),
+            ),
+            e => e.into(),
+        })?;
+        let mut json = Vec::new();
+        entry.read_to_end(&mut json)?;
+        serde_json::from_slice(&json)
+            .map_err(|e| invalid(format!("Invalid {}: {}", MANIFEST_NAME, e)))?
+    };
+    if !manifest.is_intact() {
+        return Err(invalid(format!(
+            "The digest of {} in '{}' doesn't match its entries",
+            MANIFEST_NAME,
+            src_path.display()
+        )));
+    }
+
+    let mut expected: HashMap<&str, (u64, u32)> = manifest
+        .entries
+        .iter()
+        .map(|entry| (entry.name.as_str(), (entry.size, entry.crc32)))
+        .collect();
+    let mut flagged = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        let name = entry.name().to_string();
+        if name == MANIFEST_NAME {
+            continue;
+        }
+        let listed = expected.remove(name.as_str()) == Some((entry.size(), entry.crc32()));
+        let encrypted = entry.encrypted();
+        drop(entry);
+        // Reading an entry to the end fails when its content doesn't match its CRC-32
+        let intact = encrypted
+            || archive
+                .by_index(i)
+                .map_err(io::Error::from)
+                .and_then(|mut entry| io::copy(&mut entry, &mut io::sink()))
+                .is_ok();
+        if !listed || !intact {
+            flagged.push(name);
+        }
+    }
+    flagged.extend(expected.into_keys().map(str::to_string));
+    flagged.sort();
+    Ok(flagged)
+}
+
+#[pyfunction]
+#[pyo3(name = "verify_manifest")]
+pub fn verify_manifest_pywrapper(src_py: String) -> PyResult<Vec<String>> {
+    verify_manifest(Path::new(&src_py)).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Cheap validity check: parses the central directory without reading any entry content.
+// Returns Ok(false) for files that aren't zip archives, but still errors on I/O failures.
+pub fn is_zip(src_path: &Path) -> io::Result<bool> {
+    let file = fs::File::open(src_path)?;
+    if file.metadata()?.len() < MIN_ARCHIVE_SIZE {
+        return Ok(false);
+    }
+    match ZipArchive::new(file) {
+        Ok(_) => Ok(true),
+        Err(ZipError::Io(e)) => Err(e),
+        Err(_) => Ok(false),
+    }
+}
+
+#[pyfunction]
+#[pyo3(name = "is_zip")]
+pub fn is_zip_pywrapper(src_py: String) -> PyResult<bool> {
+    is_zip(Path::new(&src_py)).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// One entry as recorded in the archive's central directory
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub compressed_size: u64,
+    // Readable compression method name, e.g. "stored", "deflate", "bzip2" or "zstd"
+    pub compression: String,
+}
+
+// Describe every entry of an open archive without decompressing anything
+pub(crate) fn read_entries<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        // Raw access only reads the headers
+        let entry = archive.by_index_raw(i)?;
+        entries.push(ArchiveEntry {
+            name: entry.name().to_string(),
+            is_dir: entry.is_dir(),
+            size: entry.size(),
+            compressed_size: entry.compressed_size(),
+            compression: compression_method_name(entry.compression()),
+        });
+    }
+    Ok(entries)
+}
+
+// List every entry of the archive in central directory order
+pub fn list_archive(src_path: &Path) -> io::Result<Vec<ArchiveEntry>> {
+    read_entries(&mut open_archive(src_path)?)
+}
+
+// Returns `(name, size, compressed_size, compression)` tuples
+#[pyfunction]
+#[pyo3(name = "list_archive")]
+pub fn list_archive_pywrapper(src_py: String) -> PyResult<Vec<(String, u64, u64, String)>> {
+    let entries =
+        list_archive(Path::new(&src_py)).map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(entries
+        .into_iter()
+        .map(|e| (e.name, e.size, e.compressed_size, e.compression))
+        .collect())
+}
+
+// Error for a failed lookup of entry `name` in the archive at `src_path`
+fn entry_lookup_error(error: ZipError, src_path: &Path, name: &str) -> io::Error {
+    match error {
+        ZipError::FileNotFound => io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Entry '{}' not found in '{}'", name, src_path.display()),
+        ),
+        other => io::Error::from(other),
+    }
+}
+
+// Chunk size used when streaming a single entry out of an archive
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// Stream one entry into `writer` chunk by chunk, never holding the whole entry in memory.
+// A running CRC-32 is checked against the archived one once the entry ends; on a mismatch
+// the corrupt bytes have already reached `writer`. Returns the number of bytes written.
+pub fn extract_entry_to<W: Write>(src_path: &Path, name: &str, writer: &mut W) -> io::Result<u64> {
+    let mut archive = open_archive(src_path)?;
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| entry_lookup_error(e, src_path, name))?;
+
+    let expected_crc = entry.crc32();
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut written = 0u64;
+    loop {
+        let read = match entry.read(&mut buffer) {
+            Ok(read) => read,
+            // The zip reader fails its own checksum test on the final read; treat that as the
+            // end of the entry so the mismatch is reported below with the entry name
+            Err(e) if e.kind() == io::ErrorKind::InvalidData && written == entry.size() => 0,
+            Err(e) => return Err(e),
+        };
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+        written += read as u64;
+    }
+    writer.flush()?;
+
+    let actual_crc = hasher.finalize();
+    if actual_crc != expected_crc {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "CRC-32 mismatch for entry '{}': expected {:08x}, got {:08x}",
+                name, expected_crc, actual_crc
+            ),
+        ));
+    }
+    Ok(written)
+}
+
+// Stream the entry called `name` into `writer`, or the archive's only file entry when no name
+// is given. Errors when there's no name and the archive holds more (or fewer) than one file.
+pub fn extract_single_entry_to<W: Write>(
+    src_path: &Path,
+    name: Option<&str>,
+    writer: &mut W,
+) -> io::Result<u64> {
+    if let Some(name) = name {
+        return extract_entry_to(src_path, name, writer);
+    }
+    let archive = open_archive(src_path)?;
+    let files: Vec<&str> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .collect();
+    match files.as_slice() {
+        [only] => {
+            let only = only.to_string();
+            drop(archive);
+            extract_entry_to(src_path, &only, writer)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Archive '{}' holds {} files; name the entry to extract",
+                src_path.display(),
+                files.len()
+            ),
+        )),
+    }
+}
+
+// Adapts a Python file-like object so its `write(bytes)` method can be used as `io::Write`,
+// and its `seek(offset, whence)` method as `io::Seek`
+pub(crate) struct PyFileWriter {
+    file: PyObject,
+}
+
+impl PyFileWriter {
+    pub(crate) fn new(file: PyObject) -> Self {
+        PyFileWriter { file }
+    }
+}
+
+impl Write for PyFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Python::with_gil(|py| {
+            let written = self
+                .file
+                .call_method1(py, "write", (PyBytes::new(py, buf),))?;
+            // Raw streams may report a short write; buffered ones return None or the full length
+            if written.is_none(py) {
+                Ok(buf.len())
+            } else {
+                written.extract::<usize>(py)
+            }
+        })
+        .map_err(|e| io::Error::other(format!("Python write() failed: {}", e)))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for PyFileWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, 0),
+            SeekFrom::Current(offset) => (offset, 1),
+            SeekFrom::End(offset) => (offset, 2),
+        };
+        Python::with_gil(|py| {
+            self.file
+                .call_method1(py, "seek", (offset, whence))?
+                .extract::<u64>(py)
+        })
+        .map_err(|e| io::Error::other(format!("Python seek() failed: {}", e)))
+    }
+}
+
+#[pyfunction]
+#[pyo3(name = "extract_entry_to")]
+pub fn extract_entry_to_pywrapper(src_py: String, name: String, writer: PyObject) -> PyResult<u64> {
+    let mut writer = PyFileWriter::new(writer);
+    extract_entry_to(Path::new(&src_py), &name, &mut writer)
+        .map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Aggregate figures describing a whole archive
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveInfo {
+    pub entry_count: usize,
+    pub total_size: u64,
+    pub total_compressed_size: u64,
+    pub comment: String,
+    // Bytes before the archive itself, e.g. the executable stub of a self-extracting archive
+    pub prefix_len: u64,
+    // Provenance found in the comment of archives written with `ZipOptions::embed_metadata`
+    pub metadata: Option<ArchiveMetadata>,
+}
+
+impl ArchiveInfo {
+    // Compressed size as a fraction of the uncompressed size (1.0 for an empty archive)
+    pub fn ratio(&self) -> f64 {
+        if self.total_size == 0 {
+            1.0
+        } else {
+            self.total_compressed_size as f64 / self.total_size as f64
+        }
+    }
+}
+
+pub fn archive_info(src_path: &Path) -> io::Result<ArchiveInfo> {
+    let mut archive = open_archive(src_path)?;
+    let entries = read_entries(&mut archive)?;
+    Ok(ArchiveInfo {
+        entry_count: entries.len(),
+        total_size: entries.iter().map(|e| e.size).sum(),
+        total_compressed_size: entries.iter().map(|e| e.compressed_size).sum(),
+        comment: String::from_utf8_lossy(archive.comment()).into_owned(),
+        prefix_len: archive.offset(),
+        metadata: ArchiveMetadata::from_comment(archive.comment()),
+    })
+}
+
+// Space needed to extract the archive: the uncompressed sizes of all entries, read from the
+// central directory without touching any content. Directory entries count as zero. Sizes
+// are untrusted, so a total overflowing u64 is reported as invalid data.
+pub fn total_uncompressed_size(src_path: &Path) -> io::Result<u64> {
+    let mut archive = open_archive(src_path)?;
+    let mut total = 0u64;
+    for i in 0..archive.len() {
+        total = total
+            .checked_add(archive.by_index_raw(i)?.size())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Total uncompressed size of the entries overflows",
+                )
+            })?;
+    }
+    Ok(total)
+}
+
+#[pyfunction]
+#[pyo3(name = "total_uncompressed_size")]
+pub fn total_uncompressed_size_pywrapper(src_py: String) -> PyResult<u64> {
+    total_uncompressed_size(Path::new(&src_py)).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Bytes available to unprivileged users on the filesystem holding `path`, or its nearest
+// existing ancestor when `path` hasn't been created yet
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."));
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: an all-zero statvfs is a valid value for statvfs() to overwrite
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat points to a live statvfs
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+// Entry names that differ between two archives, each list sorted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArchiveDiff {
+    pub only_in_a: Vec<String>,
+    pub only_in_b: Vec<String>,
+    // Present in both with a different CRC-32 or uncompressed size
+    pub changed: Vec<String>,
+}
+
+// Size and CRC-32 of every entry, read from the central directory only
+fn entry_checksums(src_path: &Path) -> io::Result<BTreeMap<String, (u64, u32)>> {
+    let mut archive = open_archive(src_path)?;
+    let mut checksums = BTreeMap::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        checksums.insert(entry.name().to_string(), (entry.size(), entry.crc32()));
+    }
+    Ok(checksums)
+}
+
+// Compare two archives by the sizes and CRCs they record, without decompressing anything
+pub fn diff_archives(a_path: &Path, b_path: &Path) -> io::Result<ArchiveDiff> {
+    let a = entry_checksums(a_path)?;
+    let b = entry_checksums(b_path)?;
+    let mut diff = ArchiveDiff::default();
+    for (name, checksum) in &a {
+        match b.get(name) {
+            None => diff.only_in_a.push(name.clone()),
+            Some(other) if other != checksum => diff.changed.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.only_in_b = b.into_keys().filter(|name| !a.contains_key(name)).collect();
+    Ok(diff)
+}
+
+// Returns a dict with "only_in_a", "only_in_b" and "changed" lists of entry names
+#[pyfunction]
+#[pyo3(name = "diff_archives")]
+pub fn diff_archives_pywrapper(
+    a_py: String,
+    b_py: String,
+) -> PyResult<HashMap<String, Vec<String>>> {
+    let diff = diff_archives(Path::new(&a_py), Path::new(&b_py))
+        .map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(HashMap::from([
+        ("only_in_a".to_string(), diff.only_in_a),
+        ("only_in_b".to_string(), diff.only_in_b),
+        ("changed".to_string(), diff.changed),
+    ]))
+}
+
+// The top-level directory shared by every entry, if the archive wraps all of its content in
+// exactly one. A file at the archive root or a second top-level component rules it out.
+fn single_root<R: Read + Seek>(archive: &mut ZipArchive<R>) -> io::Result<Option<PathBuf>> {
+    let mut root: Option<PathBuf> = None;
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        let Some(path) = entry.enclosed_name() else {
+            continue;
+        };
+        let mut components = path.components();
+        let Some(first) = components.next() else {
+            continue;
+        };
+        if components.next().is_none() && !entry.is_dir() {
+            return Ok(None);
+        }
+        let first = Path::new(first.as_os_str());
+        match &root {
+            Some(root) if root != first => return Ok(None),
+            Some(_) => {}
+            None => root = Some(first.to_path_buf()),
+        }
+    }
+    Ok(root)
+}
+
+// Pre-flight check for untrusted archives: names of the entries that would land outside the
+// destination (absolute paths, `..` traversal), in archive order. Nothing is extracted, and
+// `unzip_files` skips these entries anyway. An empty list means every entry is safe.
+pub fn check_safe(src_path: &Path) -> io::Result<Vec<String>> {
+    let mut archive = open_archive(src_path)?;
+    let mut unsafe_names = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if entry.enclosed_name().is_none() {
+            unsafe_names.push(entry.name().to_string());
+        }
+    }
+    Ok(unsafe_names)
+}
+
+#[pyfunction]
+#[pyo3(name = "check_safe")]
+pub fn check_safe_pywrapper(src_py: String) -> PyResult<Vec<String>> {
+    check_safe(Path::new(&src_py)).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Immediate children of the archive root: the first path component of every entry, with a
+// trailing slash when it's a directory (explicit or implied by a deeper entry). Sorted, no repeats.
+pub fn list_top_level(src_path: &Path) -> io::Result<Vec<String>> {
+    let archive = open_archive(src_path)?;
+    let mut top_level = BTreeSet::new();
+    for name in archive.file_names() {
+        let name = name.trim_start_matches('/');
+        match name.split_once('/') {
+            Some((first, _)) if !first.is_empty() => {
+                top_level.insert(format!("{}/", first));
+            }
+            Some(_) => {}
+            None if !name.is_empty() => {
+                top_level.insert(name.to_string());
+            }
+            None => {}
+        }
+    }
+    Ok(top_level.into_iter().collect())
+}
+
+#[pyfunction]
+#[pyo3(name = "list_top_level")]
+pub fn list_top_level_pywrapper(src_py: String) -> PyResult<Vec<String>> {
+    list_top_level(Path::new(&src_py)).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Read every file entry into memory in archive order and hand it to `callback(name, bytes)`,
+// without writing anything to disk. Directories are skipped. Iteration stops early once the
+// callback returns `Ok(false)`. Returns the number of entries passed to the callback.
+pub fn for_each_entry(
+    src_path: &Path,
+    mut callback: impl FnMut(&str, Vec<u8>) -> io::Result<bool>,
+) -> io::Result<usize> {
+    let mut archive = open_archive(src_path)?;
+    let mut visited = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let mut content =
+            Vec::with_capacity(entry.size().min(DEFAULT_READ_BUFFER_HINT as u64) as usize);
+        entry.read_to_end(&mut content)?;
+        visited += 1;
+        if !callback(entry.name(), content)? {
+            break;
+        }
+    }
+    Ok(visited)
+}
+
+// Calls `callback(name, bytes)` for every file entry; returning `False` stops the iteration.
+// Any other return value, including None, continues.
+#[pyfunction]
+#[pyo3(name = "for_each_entry")]
+pub fn for_each_entry_pywrapper(src_py: String, callback: PyObject) -> PyResult<usize> {
+    for_each_entry(Path::new(&src_py), |name, content| {
+        Python::with_gil(|py| {
+            let result = callback.call1(py, (name, PyBytes::new(py, &content)))?;
+            // Only an explicit False stops, so callbacks that return nothing visit every entry
+            Ok(!result
+                .bind(py)
+                .downcast::<pyo3::types::PyBool>()
+                .is_ok_and(|flag| !flag.is_true()))
+        })
+        .map_err(|e: PyErr| io::Error::other(format!("callback failed for '{}': {}", name, e)))
+    })
+    .map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Decompress entry `name` as UTF-8 text and hand it to `callback(line)` one line at a time,
+// without its "\n" or "\r\n" ending, so only a chunk of the entry is ever in memory. Stops
+// early once the callback returns `Ok(false)`. Returns the number of lines passed to it.
+pub fn read_entry_lines(
+    src_path: &Path,
+    name: &str,
+    mut callback: impl FnMut(&str) -> io::Result<bool>,
+) -> io::Result<usize> {
+    let mut archive = open_archive(src_path)?;
+    let entry = archive
+        .by_name(name)
+        .map_err(|e| entry_lookup_error(e, src_path, name))?;
+    let mut visited = 0;
+    for line in BufReader::with_capacity(STREAM_CHUNK_SIZE, entry).lines() {
+        let line = line.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read a line of '{}': {}", name, e),
+            )
+        })?;
+        visited += 1;
+        if !callback(&line)? {
+            break;
+        }
+    }
+    Ok(visited)
+}
+
+// Calls `callback(line)` for every line of one text entry; returning `False` stops reading.
+// Any other return value, including None, continues.
+#[pyfunction]
+#[pyo3(name = "read_entry_lines")]
+pub fn read_entry_lines_pywrapper(
+    src_py: String,
+    name: String,
+    callback: PyObject,
+) -> PyResult<usize> {
+    read_entry_lines(Path::new(&src_py), &name, |line| {
+        Python::with_gil(|py| {
+            let result = callback.call1(py, (line,))?;
+            Ok(!result
+                .bind(py)
+                .downcast::<pyo3::types::PyBool>()
+                .is_ok_and(|flag| !flag.is_true()))
+        })
+        .map_err(|e: PyErr| io::Error::other(format!("callback failed for '{}': {}", name, e)))
+    })
+    .map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+// Reassemble a file written by `zip_large_parallel` into `dst_path`, decompressing the
+// segments in parallel. Returns the path of the reassembled file.
+pub fn unzip_large_parallel(src_path: &Path, dst_path: &Path) -> io::Result<PathBuf> {
+    let invalid_data = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut archive = ZipArchive::new(fs::File::open(src_path)?)?;
+    let manifest_suffix = format!("{}{}", SEGMENT_DIR_SUFFIX, SEGMENT_MANIFEST_NAME);
+    let manifest_name = archive
+        .file_names()
+        .find(|name| name.ends_with(&manifest_suffix))
+        .map(str::to_string)
+        .ok_or_else(|| invalid_data("Archive has no segment manifest".to_string()))?;
+
+    let mut manifest_entry = archive.by_name(&manifest_name)?;
+    let mode = manifest_entry.unix_mode();
+    let mut manifest = String::new();
+    manifest_entry.read_to_string(&mut manifest)?;
+    drop(manifest_entry);
+
+    let field = |key: &str| -> io::Result<&str> {
+        manifest
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .ok_or_else(|| invalid_data(format!("Segment manifest is missing '{}'", key)))
+    };
+    let parse_number = |key: &str| -> io::Result<u64> {
+        field(key)?
+            .parse()
+            .map_err(|e| invalid_data(format!("Invalid '{}' in segment manifest: {}", key, e)))
+    };
+    let name = field("name")?;
+    let size = parse_number("size")?;
+    let segment_size = parse_number("segment_size")?;
+    let segments = parse_number("segments")?;
+
+    // The name comes from the archive, so make sure it can't escape the destination
+    if Path::new(name).file_name() != Some(name.as_ref()) {
+        return Err(invalid_data(format!(
+            "Invalid file name in segment manifest: '{}'",
+            name
+        )));
+    }
+    // Every segment but the last fills its slot exactly, so the counts must agree
+    if segment_size == 0 || segments != size.div_ceil(segment_size) {
+        return Err(invalid_data(format!(
+            "Inconsistent segment manifest: {} segments of {} bytes for {} bytes",
+            segments, segment_size, size
+        )));
+    }
+
+    fs::create_dir_all(dst_path)?;
+    let outpath = dst_path.join(name);
+    let outfile = create_output_file(&outpath, mode)?;
+    outfile.set_len(size)?;
+
+    // Each worker opens its own handle on the archive since `ZipArchive` reads are stateful
+    (0..segments).into_par_iter().try_for_each(|index| {
+        let mut archive = ZipArchive::new(fs::File::open(src_path)?)?;
+        let segment_name = format!("{}{}{:06}", name, SEGMENT_DIR_SUFFIX, index);
+        let mut segment = archive.by_name(&segment_name).map_err(|e| {
+            invalid_data(format!("Failed to read segment '{}': {}", segment_name, e))
+        })?;
+        let offset = index
+            .checked_mul(segment_size)
+            .filter(|&offset| offset < size)
+            .ok_or_else(|| invalid_data(format!("Segment '{}' is out of range", segment_name)))?;
+        let expected_len = segment_size.min(size - offset);
+        if segment.size() != expected_len {
+            return Err(invalid_data(format!(
+                "Segment '{}' holds {} bytes instead of {}",
+                segment_name,
+                segment.size(),
+                expected_len
+            )));
+        }
+        // Read one byte past the expected length to catch content longer than recorded
+        let mut content =
+            Vec::with_capacity(expected_len.min(DEFAULT_READ_BUFFER_HINT as u64) as usize);
+        (&mut segment)
+            .take(expected_len + 1)
+            .read_to_end(&mut content)?;
+        if content.len() as u64 != expected_len {
+            return Err(invalid_data(format!(
+                "Segment '{}' holds {} bytes instead of {}",
+                segment_name,
+                content.len(),
+                expected_len
+            )));
+        }
+        outfile.write_all_at(&content, offset)
+    })?;
+
+    if let Some(mode) = mode {
+        fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(outpath)
+}
+
+#[pyfunction]
+#[pyo3(name = "unzip_large_parallel")]
+pub fn unzip_large_parallel_pywrapper(src_py: String, dst_py: String) -> PyResult<String> {
+    let outpath = unzip_large_parallel(Path::new(&src_py), Path::new(&dst_py))
+        .map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(outpath.to_string_lossy().into_owned())
+}
+
+#[pyfunction]
+#[pyo3(name = "unzip_files", signature = (src_py, dst_py, preserve_ownership = false, read_buffer_hint = DEFAULT_READ_BUFFER_HINT, force_extract_mode = None, force_extract_dir_mode = None, transform = None, report_path = None, skip_identical = false, strict_symlinks = false, ledger_path = None, continue_on_error = false, auto_strip_root = false, preserve_hardlinks = false, retries = 0, mode_filter = None, mode_mask = None, max_entry_size = None, oversized_entries = "error".to_string(), preserve_atime = false, write_concurrency = None, preserve_xattrs = false, name_map = None, backup = false, order = "archive".to_string(), password = None, max_entries = None, on_duplicate = "last".to_string(), tolerant_metadata = false, preserve_birthtime = false, preserve_special_bits = false))]
+#[allow(clippy::too_many_arguments)]
+pub fn unzip_files_pywrapper(
+    src_py: String,
+    dst_py: String,
+    preserve_ownership: bool,
+    read_buffer_hint: usize,
+    force_extract_mode: Option<u32>,
+    force_extract_dir_mode: Option<u32>,
+    transform: Option<PyObject>,
+    report_path: Option<String>,
+    skip_identical: bool,
+    strict_symlinks: bool,
+    ledger_path: Option<String>,
+    continue_on_error: bool,
+    auto_strip_root: bool,
+    preserve_hardlinks: bool,
+    retries: usize,
+    mode_filter: Option<u32>,
+    mode_mask: Option<u32>,
+    max_entry_size: Option<u64>,
+    oversized_entries: String,
+    preserve_atime: bool,
+    write_concurrency: Option<usize>,
+    preserve_xattrs: bool,
+    name_map: Option<PyObject>,
+    backup: bool,
+    order: String,
+    password: Option<String>,
+    max_entries: Option<usize>,
+    on_duplicate: String,
+    tolerant_metadata: bool,
+    preserve_birthtime: bool,
+    preserve_special_bits: bool,
+) -> PyResult<Vec<(String, String)>> {
+    let src_path = PathBuf::from(src_py);
+    let dst_path = PathBuf::from(dst_py);
+    let oversized_entries = OversizePolicy::from_str(&oversized_entries, true)
+        .map_err(|e| PyIOError::new_err(format!("Invalid oversize policy: {}", e)))?;
+    let order = ExtractOrder::from_str(&order, true)
+        .map_err(|e| PyIOError::new_err(format!("Invalid extraction order: {}", e)))?;
+    let on_duplicate = DuplicateEntries::from_str(&on_duplicate, true)
+        .map_err(|e| PyIOError::new_err(format!("Invalid duplicate policy: {}", e)))?;
+
+    let options = UnzipOptions {
+        preserve_ownership,
+        read_buffer_hint,
+        force_extract_mode,
+        force_extract_dir_mode,
+        transform: transform.map(ContentTransform::from_py),
+        report_path: report_path.map(PathBuf::from),
+        skip_identical,
+        strict_symlinks,
+        ledger_path: ledger_path.map(PathBuf::from),
+        continue_on_error,
+        auto_strip_root,
+        preserve_hardlinks,
+        retries,
+        mode_filter,
+        mode_mask,
+        max_entry_size,
+        oversized_entries,
+        preserve_atime,
+        write_concurrency,
+        preserve_xattrs,
+        name_map: name_map.map(NameMap::from_py),
+        backup,
+        order,
+        password,
+        max_entries,
+        on_duplicate,
+        tolerant_metadata,
+        preserve_birthtime,
+        preserve_special_bits,
+    };
+
+    // Failed entries come back as (entry_name, error) pairs
+    let failures = unzip_files_with_options(&src_path, &dst_path, &options)
+        .map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(failures.into_iter().map(|f| (f.name, f.error)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*; // For unzip_files (PyO3 wrapper) and do_unzip_internal
+    use crate::zip::{
+        zip_files, zip_files_with_options, zip_large_parallel, ArchiveFormat, Compression,
+        EncryptionMethod, ZipOptions,
+    };
+    use std::fs::{self};
+    use std::io::Read as StdRead;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::fs::PermissionsExt as OsUnixPermissionsExt;
+    use tempfile::tempdir;
+
+    // Helper to call the internal unzip function for tests that want io::Result
+    fn unzip_files_internal_wrapper(src: &Path, dst: &Path) -> io::Result<()> {
+        super::unzip_files(src, dst)
+    }
+
+    // Helper to call the PyO3 wrapped unzip function
+    fn unzip_files_py_wrapper_local(src: String, dst: String) -> PyResult<()> {
+        super::unzip_files_pywrapper(
+            src,
+            dst,
+            false,
+            DEFAULT_READ_BUFFER_HINT,
+            None,
+            None,
+            None,
+            None,
+            false,
+            false,
+            None,
+            false,
+            false,
+            false,
+            0,
+            None,
+            None,
+            None,
+            "error".to_string(),
+            false,
+            None,
+            false,
+            None,
+            false,
+            "archive".to_string(),
+            None,
+            None,
+            "last".to_string(),
+            false,
+            false,
+            false,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unzip_files_basic() {
+        let original_dir = tempdir().unwrap();
+        let zip_file_path = original_dir.path().join("archive.zip");
+        let extracted_dir = tempdir().unwrap();
+
+        let file1_path = original_dir.path().join("file1.txt");
+        let subdir_path = original_dir.path().join("subdir");
+        let subfile_path = subdir_path.join("subfile.txt");
+
+        fs::write(&file1_path, "hello from file1").unwrap();
+        fs::create_dir(&subdir_path).unwrap();
+        fs::write(&subfile_path, "hello from subfile").unwrap();
+
+        let mut perms_file1 = fs::metadata(&file1_path).unwrap().permissions();
+        // Use the explicitly imported trait for set_mode
+        OsUnixPermissionsExt::set_mode(&mut perms_file1, 0o644);
+        fs::set_permissions(&file1_path, perms_file1).unwrap();
+
+        let mut perms_subfile = fs::metadata(&subfile_path).unwrap().permissions();
+        // Use the explicitly imported trait for set_mode
+        OsUnixPermissionsExt::set_mode(&mut perms_subfile, 0o755);
+        fs::set_permissions(&subfile_path, perms_subfile).unwrap();
+
+        let srcs_to_zip_str = vec![
+            file1_path.to_str().unwrap().to_string(),
+            subdir_path.to_str().unwrap().to_string(),
+        ];
+        let srcs_to_zip_pathbuf: Vec<PathBuf> =
+            srcs_to_zip_str.into_iter().map(PathBuf::from).collect();
+
+        // Call the internal zip_files function directly
+        zip_files(&zip_file_path, &srcs_to_zip_pathbuf, Compression::default()).unwrap();
+
+        // Test the PyO3 wrapper for unzipping
+        unzip_files_py_wrapper_local(
+            zip_file_path.to_str().unwrap().to_string(),
+            extracted_dir.path().to_str().unwrap().to_string(),
+        )
+        .unwrap();
+
+        let extracted_file1 = extracted_dir.path().join("file1.txt");
+        let extracted_subdir = extracted_dir.path().join("subdir");
+        let extracted_subfile = extracted_subdir.join("subfile.txt");
+
+        assert!(extracted_file1.exists());
+        assert!(extracted_subdir.is_dir());
+        assert!(extracted_subfile.exists());
+
+        let mut content_file1 = String::new();
+        fs::File::open(&extracted_file1)
+            .unwrap()
+            .read_to_string(&mut content_file1)
+            .unwrap();
+        assert_eq!(content_file1, "hello from file1");
+
+        let mut content_subfile = String::new();
+        fs::File::open(&extracted_subfile)
+            .unwrap()
+            .read_to_string(&mut content_subfile)
+            .unwrap();
+        assert_eq!(content_subfile, "hello from subfile");
+
+        #[cfg(unix)]
+        {
+            // Use the explicitly imported trait for mode()
+            let perms_ext_file1 =
+                OsUnixPermissionsExt::mode(&fs::metadata(&extracted_file1).unwrap().permissions());
+            assert_eq!(perms_ext_file1 & 0o777, 0o644);
+
+            let perms_ext_subfile = OsUnixPermissionsExt::mode(
+                &fs::metadata(&extracted_subfile).unwrap().permissions(),
+            );
+            assert_eq!(perms_ext_subfile & 0o777, 0o755);
+        }
+
+        // Optionally, test internal unzip function
+        let extracted_dir_internal = tempdir().unwrap();
+        unzip_files_internal_wrapper(&zip_file_path, extracted_dir_internal.path()).unwrap();
+        // Add assertions for internal version similar to above
+        assert!(extracted_dir_internal.path().join("file1.txt").exists());
+    }
+
+    #[test]
+    fn test_unzip_to_non_existent_destination() {
+        let original_dir = tempdir().unwrap();
+        let zip_file_path = original_dir.path().join("archive.zip");
+        let extracted_base_dir = tempdir().unwrap();
+        let extracted_dir_path = extracted_base_dir.path().join("new_dest_dir");
+
+        let file1_path = original_dir.path().join("dummy.txt");
+        fs::write(&file1_path, "dummy content").unwrap();
+        let srcs_to_zip_str = vec![file1_path.to_str().unwrap().to_string()];
+        let srcs_to_zip_pathbuf: Vec<PathBuf> =
+            srcs_to_zip_str.into_iter().map(PathBuf::from).collect();
+
+        zip_files(&zip_file_path, &srcs_to_zip_pathbuf, Compression::default()).unwrap();
+
+        let result = unzip_files_py_wrapper_local(
+            zip_file_path.to_str().unwrap().to_string(),
+            extracted_dir_path.to_str().unwrap().to_string(),
+        );
+        assert!(result.is_ok());
+        assert!(extracted_dir_path.exists() && extracted_dir_path.is_dir());
+        assert!(extracted_dir_path.join("dummy.txt").exists());
+    }
+
+    #[test]
+    fn test_unzip_empty_directory() {
+        let original_dir = tempdir().unwrap();
+        let zip_file_path = original_dir.path().join("archive.zip");
+        let extracted_dir = tempdir().unwrap();
+
+        let empty_dir_src = original_dir.path().join("empty_folder");
+        fs::create_dir(&empty_dir_src).unwrap();
+
+        let srcs_to_zip_str = vec![empty_dir_src.to_str().unwrap().to_string()];
+        let srcs_to_zip_pathbuf: Vec<PathBuf> =
+            srcs_to_zip_str.into_iter().map(PathBuf::from).collect();
+        zip_files(&zip_file_path, &srcs_to_zip_pathbuf, Compression::default()).unwrap();
+
+        unzip_files_py_wrapper_local(
+            zip_file_path.to_str().unwrap().to_string(),
+            extracted_dir.path().to_str().unwrap().to_string(),
+        )
+        .unwrap();
+
+        let extracted_empty_dir = extracted_dir.path().join("empty_folder");
+        assert!(extracted_empty_dir.exists() && extracted_empty_dir.is_dir());
+        // Check if it's actually empty
+        assert_eq!(fs::read_dir(&extracted_empty_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_is_zip() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("file.txt");
+        fs::write(&file_path, "not a zip archive").unwrap();
+
+        let zip_file_path = dir.path().join("archive.zip");
+        zip_files(
+            &zip_file_path,
+            std::slice::from_ref(&file_path),
+            Compression::default(),
+        )
+        .unwrap();
+
+        assert!(is_zip(&zip_file_path).unwrap());
+        assert!(!is_zip(&file_path).unwrap());
+        assert!(is_zip(&dir.path().join("missing.zip")).is_err());
+
+        // The Python wrapper raises on a missing file instead of returning false
+        assert!(is_zip_pywrapper(zip_file_path.to_str().unwrap().to_string()).unwrap());
+        assert!(
+            is_zip_pywrapper(dir.path().join("missing.zip").to_str().unwrap().to_string()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_empty_input_is_not_a_zip() {
+        let dir = tempdir().unwrap();
+        let empty_path = dir.path().join("download.zip");
+        fs::write(&empty_path, b"").unwrap();
+        let truncated_path = dir.path().join("truncated.zip");
+        fs::write(&truncated_path, b"PK\x05\x06").unwrap();
+
+        assert!(!is_zip(&empty_path).unwrap());
+        assert!(!is_zip(&truncated_path).unwrap());
+
+        let extracted_dir = tempdir().unwrap();
+        let err = unzip_files_internal_wrapper(&empty_path, extracted_dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string().contains("not a zip file: empty input"),
+            "{}",
+            err
+        );
+        let err = unzip_files_internal_wrapper(&truncated_path, extracted_dir.path()).unwrap_err();
+        assert!(err.to_string().contains("only 4 bytes long"), "{}", err);
+
+        pyo3::prepare_freethreaded_python();
+        let err = unzip_files_py_wrapper_local(
+            empty_path.to_str().unwrap().to_string(),
+            extracted_dir.path().to_str().unwrap().to_string(),
+        )
+        .unwrap_err();
+        Python::with_gil(|py| assert!(err.is_instance_of::<PyIOError>(py)));
+        assert!(
+            err.to_string().contains("not a zip file: empty input"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_unzip_large_parallel_reassembles_segments() {
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("large.bin");
+        // Mix of compressible and pseudo-random data so segments compress differently
+        let mut state: u32 = 12345;
+        let content: Vec<u8> = (0..5 * 1024 * 1024 + 123)
+            .map(|i| {
+                if (i / 4096) % 2 == 0 {
+                    (i % 251) as u8
+                } else {
+                    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                    (state >> 16) as u8
+                }
+            })
+            .collect();
+        fs::write(&src_path, &content).unwrap();
+        fs::set_permissions(&src_path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let zip_file_path = dir.path().join("large.zip");
+        zip_large_parallel(&zip_file_path, &src_path, Compression::Deflate, 1024 * 1024).unwrap();
+
+        let archive = ZipArchive::new(fs::File::open(&zip_file_path).unwrap()).unwrap();
+        // Directory, manifest and six segments
+        assert_eq!(archive.len(), 8);
+
+        let extracted_dir = tempdir().unwrap();
+        let outpath = unzip_large_parallel(&zip_file_path, extracted_dir.path()).unwrap();
+
+        assert_eq!(outpath, extracted_dir.path().join("large.bin"));
+        assert_eq!(fs::read(&outpath).unwrap(), content);
+        let mode = OsUnixPermissionsExt::mode(&fs::metadata(&outpath).unwrap().permissions());
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn test_unzip_large_parallel_rejects_inconsistent_segments() {
+        let dir = tempdir().unwrap();
+        let crafted = |segments: &str, last_segment: &[u8]| {
+            let zip_path = dir.path().join("crafted.zip");
+            let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            writer
+                .start_file("big.bin.parts/manifest", options)
+                .unwrap();
+            write!(
+                writer,
+                "name=big.bin\nsize=10\nsegment_size=4\nsegments={}\n",
+                segments
+            )
+            .unwrap();
+            for (index, content) in [&b"0123"[..], b"4567", last_segment].iter().enumerate() {
+                writer
+                    .start_file(format!("big.bin.parts/{:06}", index), options)
+                    .unwrap();
+                writer.write_all(content).unwrap();
+            }
+            writer.finish().unwrap();
+            zip_path
+        };
+        let out = dir.path().join("out");
+
+        let zip_path = crafted("3", b"89");
+        unzip_large_parallel(&zip_path, &out).unwrap();
+        assert_eq!(fs::read(out.join("big.bin")).unwrap(), b"0123456789");
+
+        // Too few segments to cover the size
+        let zip_path = crafted("1", b"89");
+        let err = unzip_large_parallel(&zip_path, &out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Inconsistent"), "{}", err);
+
+        // A last segment running past the size
+        let zip_path = crafted("3", b"89abcdef");
+        let err = unzip_large_parallel(&zip_path, &out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("instead of 2"), "{}", err);
+    }
+
+    fn zip_with_ownership(dir: &Path, file_path: &Path) -> PathBuf {
+        let zip_file_path = dir.join("owned.zip");
+        let options = ZipOptions {
+            preserve_ownership: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_file_path, &[file_path.to_path_buf()], &options).unwrap();
+        zip_file_path
+    }
+
+    #[test]
+    fn test_unzip_preserve_ownership_as_root() {
+        if !running_as_root() {
+            return;
+        }
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("owned.txt");
+        fs::write(&file_path, "owned").unwrap();
+        std::os::unix::fs::chown(&file_path, Some(1234), Some(4321)).unwrap();
+        let zip_file_path = zip_with_ownership(dir.path(), &file_path);
+
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            preserve_ownership: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_file_path, extracted_dir.path(), &options).unwrap();
+
+        let metadata = fs::metadata(extracted_dir.path().join("owned.txt")).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (1234, 4321));
+    }
+
+    #[test]
+    fn test_unzip_preserve_ownership_skips_without_privileges() {
+        if running_as_root() {
+            return;
+        }
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("owned.txt");
+        fs::write(&file_path, "owned").unwrap();
+        let zip_file_path = zip_with_ownership(dir.path(), &file_path);
+
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            preserve_ownership: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_file_path, extracted_dir.path(), &options).unwrap();
+
+        let metadata = fs::metadata(extracted_dir.path().join("owned.txt")).unwrap();
+        // SAFETY: getuid has no preconditions and cannot fail
+        assert_eq!(metadata.uid(), unsafe { libc::getuid() });
+    }
+
+    #[test]
+    fn test_zip_records_ownership_extra_field() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("owned.txt");
+        fs::write(&file_path, "owned").unwrap();
+        let source_metadata = fs::metadata(&file_path).unwrap();
+        let zip_file_path = zip_with_ownership(dir.path(), &file_path);
+
+        let mut archive = ZipArchive::new(fs::File::open(&zip_file_path).unwrap()).unwrap();
+        let entry = archive.by_name("owned.txt").unwrap();
+        let ownership = entry
+            .extra_data()
+            .and_then(|extra| find_field(extra, UNIX_OWNERSHIP_ID))
+            .and_then(decode_ownership);
+        assert_eq!(
+            ownership,
+            Some((source_metadata.uid(), source_metadata.gid()))
+        );
+    }
+
+    #[test]
+    fn test_unzip_read_buffer_hint_does_not_change_output() {
+        let original_dir = tempdir().unwrap();
+        let zip_file_path = original_dir.path().join("archive.zip");
+        let content: String = (0..10_000).map(|i| format!("line {}\n", i)).collect();
+        let file_path = original_dir.path().join("lines.txt");
+        let empty_path = original_dir.path().join("empty.txt");
+        fs::write(&file_path, &content).unwrap();
+        fs::write(&empty_path, "").unwrap();
+        zip_files(
+            &zip_file_path,
+            &[file_path, empty_path],
+            Compression::default(),
+        )
+        .unwrap();
+
+        // Disabled, smaller than the entry, and the default cap
+        for read_buffer_hint in [0, 1024, DEFAULT_READ_BUFFER_HINT] {
+            let extracted_dir = tempdir().unwrap();
+            let options = UnzipOptions {
+                read_buffer_hint,
+                ..Default::default()
+            };
+            unzip_files_with_options(&zip_file_path, extracted_dir.path(), &options).unwrap();
+            assert_eq!(
+                fs::read_to_string(extracted_dir.path().join("lines.txt")).unwrap(),
+                content
+            );
+            assert_eq!(
+                fs::read(extracted_dir.path().join("empty.txt")).unwrap(),
+                Vec::<u8>::new()
+            );
+        }
+    }
+
+    #[test]
+    fn test_dedup_round_trip() {
+        let original_dir = tempdir().unwrap();
+        let project_dir = original_dir.path().join("project");
+        fs::create_dir_all(project_dir.join("vendor")).unwrap();
+        let content: String = (0..2000)
+            .map(|i| format!("{} vendored line\n", i))
+            .collect();
+        fs::write(project_dir.join("lib.txt"), &content).unwrap();
+        fs::write(project_dir.join("vendor").join("lib.txt"), &content).unwrap();
+        fs::write(project_dir.join("other.txt"), "something else").unwrap();
+
+        let plain_zip = original_dir.path().join("plain.zip");
+        let dedup_zip = original_dir.path().join("dedup.zip");
+        zip_files(
+            &plain_zip,
+            std::slice::from_ref(&project_dir),
+            Compression::default(),
+        )
+        .unwrap();
+        let options = ZipOptions {
+            dedup: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&dedup_zip, std::slice::from_ref(&project_dir), &options).unwrap();
+
+        assert!(
+            fs::metadata(&dedup_zip).unwrap().len() < fs::metadata(&plain_zip).unwrap().len(),
+            "Deduplicated archive should be smaller"
+        );
+
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&dedup_zip, extracted_dir.path()).unwrap();
+        let extracted_project = extracted_dir.path().join("project");
+        assert_eq!(
+            fs::read_to_string(extracted_project.join("lib.txt")).unwrap(),
+            content
+        );
+        assert_eq!(
+            fs::read_to_string(extracted_project.join("vendor").join("lib.txt")).unwrap(),
+            content
+        );
+        assert_eq!(
+            fs::read_to_string(extracted_project.join("other.txt")).unwrap(),
+            "something else"
+        );
+    }
+
+    #[test]
+    fn test_unzip_split_archive() {
+        let original_dir = tempdir().unwrap();
+        let file_path = original_dir.path().join("data.txt");
+        let content: String = (0..5000).map(|i| format!("{} split me\n", i)).collect();
+        fs::write(&file_path, &content).unwrap();
+
+        let whole_zip = original_dir.path().join("whole.zip");
+        zip_files(&whole_zip, &[file_path], Compression::Stored).unwrap();
+        let bytes = fs::read(&whole_zip).unwrap();
+
+        // Cut the archive into three volumes: two companions and the final `.zip`
+        let split_dir = tempdir().unwrap();
+        let third = bytes.len() / 3;
+        fs::write(split_dir.path().join("split.z01"), &bytes[..third]).unwrap();
+        fs::write(split_dir.path().join("split.z02"), &bytes[third..2 * third]).unwrap();
+        fs::write(split_dir.path().join("split.zip"), &bytes[2 * third..]).unwrap();
+
+        let mut reader = SpannedReader::open(&split_dir.path().join("split.zip")).unwrap();
+        let mut joined = Vec::new();
+        reader.read_to_end(&mut joined).unwrap();
+        assert_eq!(joined, bytes);
+
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&split_dir.path().join("split.zip"), extracted_dir.path())
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(extracted_dir.path().join("data.txt")).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn test_list_top_level() {
+        let src_dir = tempdir().unwrap();
+        let project = src_dir.path().join("a");
+        fs::create_dir_all(project.join("empty")).unwrap();
+        fs::write(project.join("b.txt"), "b").unwrap();
+        fs::write(project.join("c.txt"), "c").unwrap();
+        let loose = src_dir.path().join("d.txt");
+        fs::write(&loose, "d").unwrap();
+
+        let zip_path = src_dir.path().join("tree.zip");
+        zip_files(&zip_path, &[project, loose], Compression::Deflate).unwrap();
+
+        assert_eq!(
+            list_top_level(&zip_path).unwrap(),
+            vec!["a/".to_string(), "d.txt".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_unzip_force_extract_mode() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("nested")).unwrap();
+        fs::write(project.join("top.txt"), "top").unwrap();
+        fs::write(project.join("nested/inner.sh"), "#!/bin/sh").unwrap();
+        fs::set_permissions(
+            project.join("nested/inner.sh"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        let zip_path = original_dir.path().join("forced.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            force_extract_mode: Some(0o444),
+            force_extract_dir_mode: Some(0o555),
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+
+        let root = extracted_dir.path().join("project");
+        for file in ["top.txt", "nested/inner.sh"] {
+            let mode = fs::metadata(root.join(file)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o444, "{} should be read-only", file);
+        }
+        for dir in [root.clone(), root.join("nested")] {
+            let mode = fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o555);
+        }
+
+        // Let the tempdir clean up after itself
+        for dir in [root.clone(), root.join("nested")] {
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unzip_transform_callback() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("a.txt"), "alpha").unwrap();
+        fs::write(project.join("b.txt"), "beta").unwrap();
+        fs::write(project.join("skip.tmp"), "scratch").unwrap();
+
+        let zip_path = original_dir.path().join("marked.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let transform = py
+                .eval(
+                    c"lambda name, data: None if name.endswith('.tmp') else data + b'|marked'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .unbind();
+            super::unzip_files_pywrapper(
+                zip_path.to_str().unwrap().to_string(),
+                extracted_dir.path().to_str().unwrap().to_string(),
+                false,
+                DEFAULT_READ_BUFFER_HINT,
+                None,
+                None,
+                Some(transform),
+                None,
+                false,
+                false,
+                None,
+                false,
+                false,
+                false,
+                0,
+                None,
+                None,
+                None,
+                "error".to_string(),
+                false,
+                None,
+                false,
+                None,
+                false,
+                "archive".to_string(),
+                None,
+                None,
+                "last".to_string(),
+                false,
+                false,
+                false,
+            )
+            .unwrap();
+        });
+
+        let root = extracted_dir.path().join("project");
+        assert_eq!(
+            fs::read_to_string(root.join("a.txt")).unwrap(),
+            "alpha|marked"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("b.txt")).unwrap(),
+            "beta|marked"
+        );
+        assert!(!root.join("skip.tmp").exists());
+    }
+
+    #[test]
+    fn test_list_archive_reports_compression_method() {
+        let original_dir = tempdir().unwrap();
+        let stored_src = original_dir.path().join("stored.txt");
+        let deflated_src = original_dir.path().join("deflated.txt");
+        fs::write(&stored_src, "stored ".repeat(50)).unwrap();
+        fs::write(&deflated_src, "deflated ".repeat(50)).unwrap();
+
+        // Two single-file archives merged into one, so each entry keeps its own method
+        let stored_zip = original_dir.path().join("stored.zip");
+        let deflated_zip = original_dir.path().join("deflated.zip");
+        zip_files(&stored_zip, &[stored_src], Compression::Stored).unwrap();
+        zip_files(&deflated_zip, &[deflated_src], Compression::Deflate).unwrap();
+        let mixed_zip = original_dir.path().join("mixed.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&mixed_zip).unwrap());
+        for part in [&stored_zip, &deflated_zip] {
+            writer
+                .merge_archive(ZipArchive::new(fs::File::open(part).unwrap()).unwrap())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let entries = list_archive(&mixed_zip).unwrap();
+        assert_eq!(entries.len(), 2);
+        let method_of = |name: &str| {
+            entries
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| e.compression.clone())
+                .unwrap()
+        };
+        assert_eq!(method_of("stored.txt"), "stored");
+        assert_eq!(method_of("deflated.txt"), "deflate");
+        assert_eq!(entries[0].size, 350);
+    }
+
+    #[test]
+    fn test_unzip_skip_identical_keeps_unchanged_files() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("same.txt"), "unchanged content").unwrap();
+        fs::write(project.join("edited.txt"), "archived content").unwrap();
+
+        let zip_path = original_dir.path().join("sync.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&zip_path, extracted_dir.path()).unwrap();
+        let root = extracted_dir.path().join("project");
+        // Same size, different bytes: only the CRC tells them apart
+        fs::write(root.join("edited.txt"), "archived CONTENT").unwrap();
+
+        // Backdate both files so any rewrite shows up as a newer mtime
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        for name in ["same.txt", "edited.txt"] {
+            fs::File::options()
+                .write(true)
+                .open(root.join(name))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+
+        let options = UnzipOptions {
+            skip_identical: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+
+        let mtime = |name: &str| fs::metadata(root.join(name)).unwrap().modified().unwrap();
+        assert_eq!(mtime("same.txt"), old);
+        assert_ne!(mtime("edited.txt"), old);
+        assert_eq!(
+            fs::read_to_string(root.join("edited.txt")).unwrap(),
+            "archived content"
+        );
+    }
+
+    #[test]
+    fn test_unzip_bzip2_and_zstd_round_trip() {
+        let original_dir = tempdir().unwrap();
+        let file_path = original_dir.path().join("compressible.txt");
+        let content = "bzip2 and zstd round trip ".repeat(400);
+        fs::write(&file_path, &content).unwrap();
+
+        for (method, name) in [(Compression::Bzip2, "bzip2"), (Compression::Zstd, "zstd")] {
+            let zip_path = original_dir.path().join(format!("{}.zip", name));
+            zip_files(&zip_path, std::slice::from_ref(&file_path), method).unwrap();
+
+            let entries = list_archive(&zip_path).unwrap();
+            assert_eq!(entries[0].compression, name);
+
+            let extracted_dir = tempdir().unwrap();
+            unzip_files_internal_wrapper(&zip_path, extracted_dir.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(extracted_dir.path().join("compressible.txt")).unwrap(),
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn test_unzip_strict_symlinks_refuses_escaping_target() {
+        let original_dir = tempdir().unwrap();
+        let zip_path = original_dir.path().join("links.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("data.txt", options).unwrap();
+        writer.write_all(b"inside").unwrap();
+        writer
+            .add_symlink("inside_link", "data.txt", options)
+            .unwrap();
+        writer
+            .add_symlink("passwd_link", "/etc/passwd", options)
+            .unwrap();
+        writer.finish().unwrap();
+
+        // Strict mode refuses the escaping link, naming both the link and its target
+        let strict_dir = tempdir().unwrap();
+        let strict = UnzipOptions {
+            strict_symlinks: true,
+            ..Default::default()
+        };
+        let err = unzip_files_with_options(&zip_path, strict_dir.path(), &strict).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("passwd_link"));
+        assert!(err.to_string().contains("/etc/passwd"));
+
+        // By default both links are recreated verbatim
+        let default_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&zip_path, default_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_link(default_dir.path().join("passwd_link")).unwrap(),
+            PathBuf::from("/etc/passwd")
+        );
+        assert_eq!(
+            fs::read_link(default_dir.path().join("inside_link")).unwrap(),
+            PathBuf::from("data.txt")
+        );
+        assert_eq!(
+            fs::read_to_string(default_dir.path().join("inside_link")).unwrap(),
+            "inside"
+        );
+    }
+
+    #[test]
+    fn test_extract_entry_to_python_bytes_io() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        // Several chunks' worth, so the loop writes more than once
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(project.join("big.bin"), &content).unwrap();
+        fs::write(project.join("other.txt"), "other").unwrap();
+
+        let zip_path = original_dir.path().join("stream.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let bytes_io = py
+                .import("io")
+                .unwrap()
+                .getattr("BytesIO")
+                .unwrap()
+                .call0()
+                .unwrap();
+            let written = extract_entry_to_pywrapper(
+                zip_path.to_str().unwrap().to_string(),
+                "project/big.bin".to_string(),
+                bytes_io.clone().unbind(),
+            )
+            .unwrap();
+            assert_eq!(written, content.len() as u64);
+
+            let value = bytes_io.call_method0("getvalue").unwrap();
+            assert_eq!(value.extract::<Vec<u8>>().unwrap(), content);
+
+            let missing = extract_entry_to_pywrapper(
+                zip_path.to_str().unwrap().to_string(),
+                "project/missing.bin".to_string(),
+                bytes_io.unbind(),
+            );
+            assert!(missing.is_err());
+        });
+    }
+
+    #[test]
+    fn test_archive_info_totals() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("a.txt"), "a".repeat(1000)).unwrap();
+        fs::write(project.join("b.txt"), "b".repeat(500)).unwrap();
+
+        let zip_path = original_dir.path().join("info.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let info = archive_info(&zip_path).unwrap();
+        // The directory entry plus two files
+        assert_eq!(info.entry_count, 3);
+        assert_eq!(info.total_size, 1500);
+        assert!(info.total_compressed_size < info.total_size);
+        assert!(info.ratio() < 1.0);
+        assert_eq!(info.comment, "");
+    }
+
+    #[test]
+    fn test_total_uncompressed_size() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("sub")).unwrap();
+        fs::write(project.join("a.txt"), "a".repeat(1234)).unwrap();
+        fs::write(project.join("sub/b.txt"), "b".repeat(4321)).unwrap();
+
+        let zip_path = original_dir.path().join("sized.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        // Two directory entries of size zero plus the two files
+        assert_eq!(total_uncompressed_size(&zip_path).unwrap(), 1234 + 4321);
+        assert!(available_space(&original_dir.path().join("not/created/yet")).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_unzip_resumes_from_ledger() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            fs::write(project.join(name), format!("content of {}", name)).unwrap();
+        }
+        let zip_path = original_dir.path().join("resume.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        let root = extracted_dir.path().join("project");
+        let ledger_path = original_dir.path().join("extract.ledger");
+        let options = UnzipOptions {
+            ledger_path: Some(ledger_path.clone()),
+            ..Default::default()
+        };
+
+        // Simulate an interruption: a directory squatting on b.txt makes the first run fail
+        fs::create_dir_all(root.join("b.txt")).unwrap();
+        assert!(unzip_files_with_options(&zip_path, extracted_dir.path(), &options).is_err());
+        let recorded = fs::read_to_string(&ledger_path).unwrap();
+        assert!(!recorded.lines().any(|name| name == "project/b.txt"));
+
+        // Anything the first run recorded must not be written again on resume
+        for name in recorded.lines() {
+            fs::write(extracted_dir.path().join(name), "already done").unwrap();
+        }
+        fs::remove_dir(root.join("b.txt")).unwrap();
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            let archive_name = format!("project/{}", name);
+            let expected = if recorded.lines().any(|n| n == archive_name) {
+                "already done".to_string()
+            } else {
+                format!("content of {}", name)
+            };
+            assert_eq!(fs::read_to_string(root.join(name)).unwrap(), expected);
+        }
+
+        // Every file is recorded exactly once across both runs
+        let mut ledger: Vec<String> = fs::read_to_string(&ledger_path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        ledger.sort();
+        assert_eq!(
+            ledger,
+            vec![
+                "project/a.txt",
+                "project/b.txt",
+                "project/c.txt",
+                "project/d.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unzip_continue_on_error_reports_failures() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        for name in ["ok1.txt", "blocked.txt", "ok2.txt"] {
+            fs::write(project.join(name), name).unwrap();
+        }
+        let zip_path = original_dir.path().join("partial.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        // A directory where a file should go can't be overwritten, even as root
+        let extracted_dir = tempdir().unwrap();
+        let root = extracted_dir.path().join("project");
+        fs::create_dir_all(root.join("blocked.txt")).unwrap();
+
+        assert!(unzip_files_internal_wrapper(&zip_path, extracted_dir.path()).is_err());
+
+        let options = UnzipOptions {
+            continue_on_error: true,
+            ..Default::default()
+        };
+        let failures = unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].name, "project/blocked.txt");
+        assert!(failures[0].error.contains("blocked.txt"));
+        assert_eq!(fs::read_to_string(root.join("ok1.txt")).unwrap(), "ok1.txt");
+        assert_eq!(fs::read_to_string(root.join("ok2.txt")).unwrap(), "ok2.txt");
+    }
+
+    #[test]
+    fn test_unzip_auto_strip_root() {
+        let original_dir = tempdir().unwrap();
+        let release = original_dir.path().join("release-1.0");
+        fs::create_dir_all(release.join("bin")).unwrap();
+        fs::write(release.join("README"), "readme").unwrap();
+        fs::write(release.join("bin/tool"), "tool").unwrap();
+        let single_root = original_dir.path().join("single.zip");
+        zip_files(
+            &single_root,
+            std::slice::from_ref(&release),
+            Compression::Deflate,
+        )
+        .unwrap();
+
+        let options = UnzipOptions {
+            auto_strip_root: true,
+            ..Default::default()
+        };
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_with_options(&single_root, extracted_dir.path(), &options).unwrap();
+        assert!(!extracted_dir.path().join("release-1.0").exists());
+        assert_eq!(
+            fs::read_to_string(extracted_dir.path().join("README")).unwrap(),
+            "readme"
+        );
+        assert_eq!(
+            fs::read_to_string(extracted_dir.path().join("bin/tool")).unwrap(),
+            "tool"
+        );
+
+        // A second top-level entry means there's no single root to strip
+        let notes = original_dir.path().join("NOTES.txt");
+        fs::write(&notes, "notes").unwrap();
+        let multi_root = original_dir.path().join("multi.zip");
+        zip_files(&multi_root, &[release, notes], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_with_options(&multi_root, extracted_dir.path(), &options).unwrap();
+        assert!(extracted_dir.path().join("release-1.0/bin/tool").exists());
+        assert!(extracted_dir.path().join("NOTES.txt").exists());
+        assert!(!extracted_dir.path().join("README").exists());
+    }
+
+    #[test]
+    fn test_extract_entry_to_detects_crc_mismatch() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        let content = b"stored bytes that will be corrupted on disk";
+        fs::write(project.join("data.bin"), content).unwrap();
+
+        let zip_path = original_dir.path().join("corrupt.zip");
+        zip_files(&zip_path, &[project], Compression::Stored).unwrap();
+
+        // Stored entries keep their content verbatim, so flip a byte of it in place
+        let mut bytes = fs::read(&zip_path).unwrap();
+        let offset = bytes
+            .windows(content.len())
+            .position(|window| window == content)
+            .unwrap();
+        bytes[offset] ^= 0xff;
+        fs::write(&zip_path, &bytes).unwrap();
+
+        let mut output = Vec::new();
+        let err = extract_entry_to(&zip_path, "project/data.bin", &mut output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("CRC-32 mismatch"));
+        assert!(err.to_string().contains("project/data.bin"));
+        assert_eq!(output.len(), content.len());
+    }
+
+    #[test]
+    fn test_unzip_preserve_hardlinks_round_trip() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("sub")).unwrap();
+        fs::write(project.join("original.txt"), "shared inode").unwrap();
+        fs::hard_link(project.join("original.txt"), project.join("sub/alias.txt")).unwrap();
+
+        let zip_path = original_dir.path().join("hardlinks.zip");
+        let zip_options = ZipOptions {
+            preserve_hardlinks: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_path, &[project], &zip_options).unwrap();
+
+        let options = UnzipOptions {
+            preserve_hardlinks: true,
+            ..Default::default()
+        };
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+        let original = fs::metadata(extracted_dir.path().join("project/original.txt")).unwrap();
+        let alias = fs::metadata(extracted_dir.path().join("project/sub/alias.txt")).unwrap();
+        assert_eq!(original.ino(), alias.ino());
+        assert_eq!(original.nlink(), 2);
+        assert_eq!(
+            fs::read_to_string(extracted_dir.path().join("project/sub/alias.txt")).unwrap(),
+            "shared inode"
+        );
+
+        // Without the option the link becomes an independent copy
+        let copied_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&zip_path, copied_dir.path()).unwrap();
+        let original = fs::metadata(copied_dir.path().join("project/original.txt")).unwrap();
+        let alias = fs::metadata(copied_dir.path().join("project/sub/alias.txt")).unwrap();
+        assert_ne!(original.ino(), alias.ino());
+        assert_eq!(
+            fs::read_to_string(copied_dir.path().join("project/sub/alias.txt")).unwrap(),
+            "shared inode"
+        );
+    }
+
+    #[test]
+    fn test_diff_archives() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("same.txt"), "unchanged").unwrap();
+        fs::write(project.join("edited.txt"), "before").unwrap();
+        fs::write(project.join("removed.txt"), "gone soon").unwrap();
+        let a_path = original_dir.path().join("a.zip");
+        zip_files(
+            &a_path,
+            std::slice::from_ref(&project),
+            Compression::Deflate,
+        )
+        .unwrap();
+
+        fs::write(project.join("edited.txt"), "after").unwrap();
+        fs::remove_file(project.join("removed.txt")).unwrap();
+        fs::write(project.join("added.txt"), "new").unwrap();
+        let b_path = original_dir.path().join("b.zip");
+        zip_files(&b_path, &[project], Compression::Stored).unwrap();
+
+        let diff = diff_archives(&a_path, &b_path).unwrap();
+        assert_eq!(diff.only_in_a, vec!["project/removed.txt"]);
+        assert_eq!(diff.only_in_b, vec!["project/added.txt"]);
+        assert_eq!(diff.changed, vec!["project/edited.txt"]);
+
+        let same = diff_archives(&a_path, &a_path).unwrap();
+        assert_eq!(same, ArchiveDiff::default());
+    }
+
+    // Writer whose first write fails with a transient error
+    struct FlakyWriter {
+        failures_left: usize,
+        written: Vec<u8>,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failures_left > 0 {
+                self.failures_left -= 1;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_retries_transient_errors() {
+        let mut writer = FlakyWriter {
+            failures_left: 1,
+            written: Vec::new(),
+        };
+        write_all_with_retries(&mut writer, b"retried", 1).unwrap();
+        assert_eq!(writer.written, b"retried");
+
+        let mut writer = FlakyWriter {
+            failures_left: 1,
+            written: Vec::new(),
+        };
+        let err = write_all_with_retries(&mut writer, b"retried", 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        // Only transient kinds are retried
+        let mut calls = 0;
+        let err = with_retries(3, || -> io::Result<()> {
+            calls += 1;
+            Err(io::ErrorKind::PermissionDenied.into())
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        with_retries(1, || {
+            calls += 1;
+            if calls == 1 {
+                Err(io::ErrorKind::Interrupted.into())
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_unzip_mode_filter_extracts_only_executables() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("bin")).unwrap();
+        fs::write(project.join("notes.txt"), "notes").unwrap();
+        fs::write(project.join("bin/run.sh"), "#!/bin/sh").unwrap();
+        fs::write(project.join("bin/data.txt"), "data").unwrap();
+        for (file, mode) in [
+            ("notes.txt", 0o644),
+            ("bin/run.sh", 0o755),
+            ("bin/data.txt", 0o600),
+        ] {
+            fs::set_permissions(project.join(file), fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let zip_path = original_dir.path().join("mixed.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            mode_filter: Some(0o100),
+            mode_mask: Some(0o100),
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+
+        let root = extracted_dir.path().join("project");
+        assert_eq!(
+            fs::read_to_string(root.join("bin/run.sh")).unwrap(),
+            "#!/bin/sh"
+        );
+        assert!(!root.join("notes.txt").exists());
+        assert!(!root.join("bin/data.txt").exists());
+    }
+
+    #[test]
+    fn test_for_each_entry_callback_and_early_stop() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("nested")).unwrap();
+        fs::write(project.join("a.txt"), "alpha").unwrap();
+        fs::write(project.join("b.txt"), "beta").unwrap();
+        fs::write(project.join("nested/c.txt"), "gamma").unwrap();
+        let zip_path = original_dir.path().join("scan.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let seen = pyo3::types::PyList::empty(py);
+            let globals = pyo3::types::PyDict::new(py);
+            globals.set_item("seen", &seen).unwrap();
+            let collect = py
+                .eval(
+                    c"lambda name, data: seen.append((name, data))",
+                    Some(&globals),
+                    None,
+                )
+                .unwrap()
+                .unbind();
+            let visited =
+                for_each_entry_pywrapper(zip_path.to_str().unwrap().to_string(), collect).unwrap();
+            assert_eq!(visited, 3);
+            let seen: Vec<(String, Vec<u8>)> = seen.extract().unwrap();
+            assert_eq!(seen.len(), 3);
+            assert!(seen.contains(&("project/a.txt".to_string(), b"alpha".to_vec())));
+            assert!(seen.contains(&("project/nested/c.txt".to_string(), b"gamma".to_vec())));
+
+            // Returning False after the second entry ends the iteration there
+            let names = pyo3::types::PyList::empty(py);
+            globals.set_item("names", &names).unwrap();
+            let stop_early = py
+                .eval(
+                    c"lambda name, data: names.append(name) or len(names) < 2",
+                    Some(&globals),
+                    None,
+                )
+                .unwrap()
+                .unbind();
+            let visited =
+                for_each_entry_pywrapper(zip_path.to_str().unwrap().to_string(), stop_early)
+                    .unwrap();
+            assert_eq!(visited, 2);
+            assert_eq!(names.len(), 2);
+        });
+
+        // Nothing is extracted next to the archive
+        let mut left: Vec<_> = fs::read_dir(original_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["project", "scan.zip"]);
+        assert_eq!(
+            fs::read_dir(original_dir.path().join("project"))
+                .unwrap()
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_unzip_max_entry_size_skips_oversized_entries() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("small.txt"), "small").unwrap();
+        fs::write(project.join("large.bin"), vec![7u8; 10_000]).unwrap();
+        let zip_path = original_dir.path().join("capped.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            max_entry_size: Some(1_000),
+            oversized_entries: OversizePolicy::Skip,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+        let root = extracted_dir.path().join("project");
+        assert_eq!(fs::read_to_string(root.join("small.txt")).unwrap(), "small");
+        assert!(!root.join("large.bin").exists());
+
+        // By default the oversized entry aborts the extraction
+        let options = UnzipOptions {
+            max_entry_size: Some(1_000),
+            ..Default::default()
+        };
+        let err =
+            unzip_files_with_options(&zip_path, tempdir().unwrap().path(), &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("project/large.bin"));
+    }
+
+    #[test]
+    fn test_unzip_max_entries_refuses_crowded_archive() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        for i in 0..5 {
+            fs::write(project.join(format!("{}.txt", i)), "tiny").unwrap();
+        }
+        let zip_path = original_dir.path().join("crowded.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        // Five files and their directory
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            max_entries: Some(5),
+            ..Default::default()
+        };
+        let err = unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string()
+                .contains("holds 6 entries, over the limit of 5"),
+            "{}",
+            err
+        );
+        assert_eq!(fs::read_dir(extracted_dir.path()).unwrap().count(), 0);
+
+        let options = UnzipOptions {
+            max_entries: Some(6),
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+        assert!(extracted_dir.path().join("project/4.txt").exists());
+    }
+
+    #[test]
+    fn test_unzip_duplicate_entry_names() {
+        let original_dir = tempdir().unwrap();
+        let zip_path = original_dir.path().join("duplicates.zip");
+        {
+            let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            for (name, content) in [
+                ("dir/same.txt", "first"),
+                ("dir/same (1).txt", "taken"),
+                ("dir/SAME.txt", "second"),
+            ] {
+                zip.start_file(name, options).unwrap();
+                zip.write_all(content.as_bytes()).unwrap();
+            }
+            zip.finish().unwrap();
+        }
+        // The zip writer refuses duplicate names, so the third entry is renamed afterwards
+        // in both its headers, like an archive with another one appended to it
+        let bytes = fs::read(&zip_path).unwrap();
+        let mut patched = Vec::with_capacity(bytes.len());
+        let mut rest = &bytes[..];
+        while let Some(at) = rest.windows(12).position(|w| w == b"dir/SAME.txt") {
+            patched.extend_from_slice(&rest[..at]);
+            patched.extend_from_slice(b"dir/same.txt");
+            rest = &rest[at + 12..];
+        }
+        patched.extend_from_slice(rest);
+        fs::write(&zip_path, patched).unwrap();
+
+        let extract = |on_duplicate| {
+            let extracted_dir = tempdir().unwrap();
+            let options = UnzipOptions {
+                on_duplicate,
+                ..Default::default()
+            };
+            unzip_files_with_options(&zip_path, extracted_dir.path(), &options)
+                .map(|_| extracted_dir)
+        };
+        let read = |dir: &tempfile::TempDir, name: &str| {
+            fs::read_to_string(dir.path().join("dir").join(name)).unwrap()
+        };
+
+        let last = extract(DuplicateEntries::Last).unwrap();
+        assert_eq!(read(&last, "same.txt"), "second");
+
+        let first = extract(DuplicateEntries::First).unwrap();
+        assert_eq!(read(&first, "same.txt"), "first");
+        assert_eq!(read(&first, "same (1).txt"), "taken");
+        assert_eq!(fs::read_dir(first.path().join("dir")).unwrap().count(), 2);
+
+        let err = extract(DuplicateEntries::Error).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string()
+                .contains("has several entries named dir/same.txt"),
+            "{}",
+            err
+        );
+
+        // The second copy skips the number another entry already has
+        let numbered = extract(DuplicateEntries::Number).unwrap();
+        assert_eq!(read(&numbered, "same.txt"), "first");
+        assert_eq!(read(&numbered, "same (1).txt"), "taken");
+        assert_eq!(read(&numbered, "same (2).txt"), "second");
+    }
+
+    #[test]
+    fn test_unzip_tolerant_metadata_keeps_content() {
+        // Setting the immutable flag, which makes chmod fail, needs root
+        if !running_as_root() {
+            return;
+        }
+
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("locked.txt"), "locked").unwrap();
+        fs::write(project.join("fresh.txt"), "fresh").unwrap();
+        let zip_path = original_dir.path().join("metadata.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&zip_path, extracted_dir.path()).unwrap();
+        let root = extracted_dir.path().join("project");
+        fs::remove_file(root.join("fresh.txt")).unwrap();
+        let locked = root.join("locked.txt");
+        let chattr = |flag: &str| {
+            std::process::Command::new("chattr")
+                .arg(flag)
+                .arg(&locked)
+                .status()
+                .is_ok_and(|status| status.success())
+        };
+        // Not every filesystem supports the flag
+        if !chattr("+i") {
+            return;
+        }
+
+        // locked.txt already holds its content, so only setting its mode fails
+        let strict = UnzipOptions {
+            skip_identical: true,
+            ..Default::default()
+        };
+        let strict_result = unzip_files_with_options(&zip_path, extracted_dir.path(), &strict);
+        let tolerant = UnzipOptions {
+            skip_identical: true,
+            tolerant_metadata: true,
+            ..Default::default()
+        };
+        let tolerant_result = unzip_files_with_options(&zip_path, extracted_dir.path(), &tolerant);
+        assert!(chattr("-i"));
+
+        let err = strict_result.unwrap_err();
+        assert!(
+            err.to_string().contains("Failed to set permissions"),
+            "{}",
+            err
+        );
+        tolerant_result.unwrap();
+        assert_eq!(fs::read_to_string(&locked).unwrap(), "locked");
+        assert_eq!(fs::read_to_string(root.join("fresh.txt")).unwrap(), "fresh");
+    }
+
+    #[test]
+    fn test_unzip_preserve_atime_from_extended_timestamp() {
+        let original_dir = tempdir().unwrap();
+        let zip_path = original_dir.path().join("atime.zip");
+        let (mtime, atime) = (1_600_000_000u32, 1_650_000_000u32);
+        {
+            let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+            // Extended timestamp: flags (mtime and atime present), then both times
+            let mut field = vec![0b011u8];
+            field.extend_from_slice(&mtime.to_le_bytes());
+            field.extend_from_slice(&atime.to_le_bytes());
+            let mut options = zip::write::FullFileOptions::default();
+            options.add_extra_data(0x5455, field.into(), false).unwrap();
+            zip.start_file("stamped.txt", options).unwrap();
+            zip.write_all(b"stamped").unwrap();
+            zip.start_file("plain.txt", zip::write::FullFileOptions::default())
+                .unwrap();
+            zip.write_all(b"plain").unwrap();
+            zip.finish().unwrap();
+        }
+
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            preserve_atime: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+
+        // Best effort: setting the atime works even on noatime mounts, reading it back
+        // right away doesn't update it
+        let stamped = fs::metadata(extracted_dir.path().join("stamped.txt")).unwrap();
+        assert_eq!(stamped.atime(), i64::from(atime));
+        let plain = fs::metadata(extracted_dir.path().join("plain.txt")).unwrap();
+        assert_ne!(plain.atime(), i64::from(atime));
+    }
+
+    #[test]
+    fn test_targz_round_trip_preserves_permissions() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("bin")).unwrap();
+        fs::write(project.join("readme.txt"), "read me").unwrap();
+        fs::write(project.join("bin/run.sh"), "#!/bin/sh").unwrap();
+        for (path, mode) in [("readme.txt", 0o640), ("bin/run.sh", 0o750), ("bin", 0o711)] {
+            fs::set_permissions(project.join(path), fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let archive_path = original_dir.path().join("project.tar.gz");
+        let options = ZipOptions {
+            format: ArchiveFormat::TarGz,
+            ..Default::default()
+        };
+        zip_files_with_options(&archive_path, std::slice::from_ref(&project), &options).unwrap();
+        assert!(!is_zip(&archive_path).unwrap());
+
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&archive_path, extracted_dir.path()).unwrap();
+
+        let root = extracted_dir.path().join("project");
+        assert_eq!(
+            fs::read_to_string(root.join("readme.txt")).unwrap(),
+            "read me"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("bin/run.sh")).unwrap(),
+            "#!/bin/sh"
+        );
+        for (path, mode) in [("readme.txt", 0o640), ("bin/run.sh", 0o750), ("bin", 0o711)] {
+            let actual = fs::metadata(root.join(path)).unwrap().permissions().mode();
+            assert_eq!(actual & 0o777, mode, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_targz_special_bits_and_unsupported_options() {
+        let original_dir = tempdir().unwrap();
+        let tool = original_dir.path().join("tool");
+        fs::write(&tool, "#!/bin/sh").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o4755)).unwrap();
+        let archive_path = original_dir.path().join("tool.tar.gz");
+        let options = ZipOptions {
+            format: ArchiveFormat::TarGz,
+            ..Default::default()
+        };
+        zip_files_with_options(&archive_path, std::slice::from_ref(&tool), &options).unwrap();
+
+        // The setuid bit is dropped unless asked for
+        let extracted_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&archive_path, extracted_dir.path()).unwrap();
+        let mode = fs::metadata(extracted_dir.path().join("tool"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o755);
+        let kept_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            preserve_special_bits: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&archive_path, kept_dir.path(), &options).unwrap();
+        let mode = fs::metadata(kept_dir.path().join("tool"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o4755);
+
+        // Options tar extraction can't honor are refused before anything is written
+        let refused_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            max_entries: Some(10),
+            strict_symlinks: true,
+            ..Default::default()
+        };
+        let err =
+            unzip_files_with_options(&archive_path, refused_dir.path(), &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(
+            err.to_string().contains("strict_symlinks, max_entries"),
+            "{}",
+            err
+        );
+        assert!(!refused_dir.path().join("tool").exists());
+
+        // Likewise the zip-only outputs when writing one
+        let options = ZipOptions {
+            format: ArchiveFormat::TarGz,
+            report_path: Some(original_dir.path().join("report.json")),
+            ..Default::default()
+        };
+        let err = zip_files_with_options(
+            &original_dir.path().join("refused.tar.gz"),
+            std::slice::from_ref(&tool),
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("report_path"), "{}", err);
+    }
+
+    #[test]
+    fn test_archive_with_prepended_stub() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("a.txt"), "alpha").unwrap();
+        let zip_path = original_dir.path().join("plain.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        // A fake executable stub in front, like a self-extracting archive, and junk after
+        let mut sfx = b"\x7fELF".to_vec();
+        sfx.extend((0..5_000u32).map(|i| (i % 251) as u8));
+        sfx.extend(fs::read(&zip_path).unwrap());
+        let sfx_path = original_dir.path().join("sfx.zip");
+        fs::write(&sfx_path, &sfx).unwrap();
+        sfx.extend(vec![0x55u8; 100_000]);
+        let trailing_path = original_dir.path().join("trailing.zip");
+        fs::write(&trailing_path, &sfx).unwrap();
+        assert_eq!(archive_info(&zip_path).unwrap().prefix_len, 0);
+        assert_eq!(archive_info(&sfx_path).unwrap().prefix_len, 5_004);
+
+        for path in [&sfx_path, &trailing_path] {
+            let names: Vec<String> = list_archive(path)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.name)
+                .collect();
+            assert_eq!(names, ["project/", "project/a.txt"], "{}", path.display());
+            let extracted_dir = tempdir().unwrap();
+            unzip_files_internal_wrapper(path, extracted_dir.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(extracted_dir.path().join("project/a.txt")).unwrap(),
+                "alpha"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_safe_lists_escaping_entries() {
+        let original_dir = tempdir().unwrap();
+        let zip_path = original_dir.path().join("mixed.zip");
+        {
+            let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            for name in [
+                "safe.txt",
+                "../escape.txt",
+                "nested/ok.txt",
+                "nested/../../up.txt",
+                "nested/../fine.txt",
+            ] {
+                zip.start_file(name, options).unwrap();
+                zip.write_all(b"content").unwrap();
+            }
+            zip.finish().unwrap();
+        }
+
+        assert_eq!(
+            check_safe(&zip_path).unwrap(),
+            ["../escape.txt", "nested/../../up.txt"]
+        );
+        // Nothing was extracted next to the archive
+        assert!(!original_dir.path().join("safe.txt").exists());
+
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("a.txt"), "alpha").unwrap();
+        let safe_path = original_dir.path().join("safe.zip");
+        zip_files(&safe_path, &[project], Compression::Deflate).unwrap();
+        assert!(check_safe(&safe_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_round_trip_with_asymmetric_concurrency() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("nested")).unwrap();
+        for i in 0..40 {
+            let dir = if i % 2 == 0 { "" } else { "nested/" };
+            fs::write(
+                project.join(format!("{}file{}.txt", dir, i)),
+                format!("content {}", i),
+            )
+            .unwrap();
+        }
+
+        let zip_path = original_dir.path().join("concurrency.zip");
+        let zip_options = ZipOptions {
+            read_concurrency: Some(1),
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_path, std::slice::from_ref(&project), &zip_options).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        let unzip_options = UnzipOptions {
+            write_concurrency: Some(6),
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &unzip_options).unwrap();
+
+        let root = extracted_dir.path().join("project");
+        for i in 0..40 {
+            let dir = if i % 2 == 0 { "" } else { "nested/" };
+            assert_eq!(
+                fs::read_to_string(root.join(format!("{}file{}.txt", dir, i))).unwrap(),
+                format!("content {}", i)
+            );
+        }
+    }
+
+    #[test]
+    fn test_xattrs_round_trip() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        let file = project.join("tagged.txt");
+        fs::write(&file, "tagged").unwrap();
+        // Best effort: skip on file systems without user xattrs
+        if xattr::set(&file, "user.ziprs.test", b"kept").is_err() {
+            return;
+        }
+
+        let zip_path = original_dir.path().join("xattrs.zip");
+        let zip_options = ZipOptions {
+            preserve_xattrs: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_path, &[project], &zip_options).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        let unzip_options = UnzipOptions {
+            preserve_xattrs: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &unzip_options).unwrap();
+        let extracted = extracted_dir.path().join("project/tagged.txt");
+        assert_eq!(
+            xattr::get(&extracted, "user.ziprs.test").unwrap(),
+            Some(b"kept".to_vec())
+        );
+
+        // Without the option nothing is restored
+        let plain_dir = tempdir().unwrap();
+        unzip_files_internal_wrapper(&zip_path, plain_dir.path()).unwrap();
+        let plain = plain_dir.path().join("project/tagged.txt");
+        assert_eq!(xattr::get(&plain, "user.ziprs.test").unwrap(), None);
+    }
+
+    #[test]
+    fn test_unzip_name_map_renames_entries() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("docs/guide")).unwrap();
+        fs::write(project.join("docs/guide/intro.md"), "intro").unwrap();
+        fs::write(project.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(project.join("notes.tmp"), "scratch").unwrap();
+        let zip_path = original_dir.path().join("renamed.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            name_map: Some(NameMap::new(|name| {
+                let name = name.strip_prefix("project/").unwrap_or(name);
+                Ok(if name.ends_with(".tmp") {
+                    None
+                } else if let Some(rest) = name.strip_prefix("docs/") {
+                    Some(format!("documentation/{}", rest))
+                } else {
+                    Some(name.to_string())
+                })
+            })),
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
 
-    let mut dirs_to_create: Vec<PathBuf> = Vec::new();
-    let mut files_to_extract: Vec<(PathBuf, Vec<u8>, Option<u32>)> = Vec::new();
+        let root = extracted_dir.path();
+        assert_eq!(
+            fs::read_to_string(root.join("documentation/guide/intro.md")).unwrap(),
+            "intro"
+        );
+        assert!(root.join("main.rs").is_file());
+        assert!(!root.join("docs").exists());
+        assert!(!root.join("project").exists());
+        assert!(!root.join("notes.tmp").exists());
 
-    // Collect all file entries first to enable parallel processing.
-    for i in 0..archive.len() {
-        // Get the file entry from the zip archive.
-        let mut file_in_zip = archive.by_index(i).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to read file in zip by index {}: {}", i, e),
-            )
-        })?;
+        // A mapped name leaving the destination is refused
+        let options = UnzipOptions {
+            name_map: Some(NameMap::new(|name| Ok(Some(format!("../{}", name))))),
+            ..Default::default()
+        };
+        let err =
+            unzip_files_with_options(&zip_path, tempdir().unwrap().path(), &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
 
-        // Get the path of the file in the zip archive.
-        let outpath = match file_in_zip.enclosed_name() {
-            Some(path) => dst_path.join(path),
-            None => continue,
+    #[test]
+    fn test_unzip_streams_more_entries_than_the_queue() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        let mut expected = Vec::new();
+        for i in 0..EXTRACT_QUEUE_LEN * 4 {
+            let rel = format!("dir{}/file{}.bin", i % 5, i);
+            let content: Vec<u8> = (0..(i * 997) as u32).map(|b| (b % 251) as u8).collect();
+            fs::create_dir_all(project.join(&rel).parent().unwrap()).unwrap();
+            fs::write(project.join(&rel), &content).unwrap();
+            expected.push((rel, content));
+        }
+        fs::create_dir(project.join("empty")).unwrap();
+        // Identical content, stored once and restored from the archive as a link
+        fs::write(project.join("copy.bin"), &expected[7].1).unwrap();
+        expected.push(("copy.bin".to_string(), expected[7].1.clone()));
+        let zip_path = original_dir.path().join("many.zip");
+        let zip_options = ZipOptions {
+            dedup: true,
+            ..Default::default()
         };
+        zip_files_with_options(&zip_path, &[project], &zip_options).unwrap();
 
-        // If the file is a directory, add it to the list of directories to create.
-        if file_in_zip.name().ends_with('/') {
-            dirs_to_create.push(outpath);
-        } else {
-            let mut content = Vec::new();
-            file_in_zip.read_to_end(&mut content).map_err(|e| {
-                io::Error::other(format!(
-                    "Failed to read file content from zip entry '{}': {}",
-                    file_in_zip.name(),
-                    e
-                ))
-            })?;
-            let mode = file_in_zip.unix_mode();
-            files_to_extract.push((outpath, content, mode));
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            write_concurrency: Some(2),
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+
+        let root = extracted_dir.path().join("project");
+        for (rel, content) in &expected {
+            assert_eq!(&fs::read(root.join(rel)).unwrap(), content, "{}", rel);
         }
+        assert!(root.join("empty").is_dir());
     }
 
-    // Create all necessary directory structures sequentially first.
-    // This avoids race conditions that might occur if directories are created in parallel
-    // with file extractions, especially for nested structures.
-    for dir_path in dirs_to_create {
-        fs::create_dir_all(&dir_path).map_err(|e| {
-            io::Error::other(format!(
-                "Failed to create directory structure at '{}': {}",
-                dir_path.display(),
-                e
-            ))
-        })?;
-    }
+    #[test]
+    fn test_entry_comments_round_trip() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("a.txt"), "alpha").unwrap();
+        fs::write(project.join("b.txt"), "beta").unwrap();
+        let zip_path = original_dir.path().join("commented.zip");
+        let options = ZipOptions {
+            comments: Some(HashMap::from([
+                ("project/a.txt".to_string(), "first file".to_string()),
+                (
+                    "project/b.txt".to_string(),
+                    "zweite Datei für dich".to_string(),
+                ),
+            ])),
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_path, &[project], &options).unwrap();
 
-    // Extract files in parallel for performance.
-    // Each file extraction is an independent operation after directories are set up.
-    // Limit the number of threads to 8 to avoid overwhelming the system
-    files_to_extract.par_iter().with_max_len(8).try_for_each(
-        |(path, content, mode_opt)| -> io::Result<()> {
-            // Ensure parent directory exists before writing the file.
-            // This is necessary because a file might be listed in the zip archive
-            // before its parent directory, or the directory creation pass might have missed it
-            // if it wasn't explicitly listed as a directory entry in the zip.
-            if let Some(p) = path.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p).map_err(|e| {
-                        io::Error::other(format!(
-                            "Failed to create parent directory for file '{}': {}",
-                            path.display(),
-                            e
-                        ))
-                    })?;
-                }
-            }
+        assert_eq!(
+            read_entry_comment(&zip_path, "project/a.txt").unwrap(),
+            "first file"
+        );
+        assert_eq!(
+            read_entry_comment(&zip_path, "project/b.txt").unwrap(),
+            "zweite Datei für dich"
+        );
+        assert_eq!(read_entry_comment(&zip_path, "project/").unwrap(), "");
+        assert_eq!(
+            read_entry_comment(&zip_path, "missing").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        let bytes = fs::read(&zip_path).unwrap();
+        assert!(!bytes.windows(3).any(|w| w == [0xEF, 0xBB, 0xBF]));
 
-            let mut outfile = fs::File::create(path).map_err(|e| {
-                io::Error::other(format!(
-                    "Failed to create output file '{}': {}",
-                    path.display(),
-                    e
-                ))
-            })?;
-            outfile.write_all(content).map_err(|e| {
-                io::Error::other(format!(
-                    "Failed to write content to file '{}': {}",
-                    path.display(),
-                    e
-                ))
-            })?;
+        // The archive stays readable end to end
+        let extracted_dir = tempdir().unwrap();
+        unzip_files(&zip_path, extracted_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(extracted_dir.path().join("project/b.txt")).unwrap(),
+            "beta"
+        );
+    }
 
-            // Set permissions if available
-            #[cfg(unix)]
-            if let Some(mode) = mode_opt {
-                fs::set_permissions(path, fs::Permissions::from_mode(*mode)).map_err(|e| {
-                    io::Error::other(format!(
-                        "Failed to set permissions on '{}': {}",
-                        path.display(),
-                        e
-                    ))
-                })?;
-            }
-            Ok(())
-        },
-    )?;
+    #[test]
+    fn test_extract_entries_writes_only_the_listed_ones() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("src/bin")).unwrap();
+        fs::create_dir_all(project.join("docs")).unwrap();
+        fs::write(project.join("README.md"), "readme").unwrap();
+        fs::write(project.join("src/lib.rs"), "lib").unwrap();
+        fs::write(project.join("src/bin/tool.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(
+            project.join("src/bin/tool.sh"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        fs::write(project.join("docs/guide.md"), "guide").unwrap();
+        fs::write(project.join("docs/faq.md"), "faq").unwrap();
+        let zip_path = original_dir.path().join("five.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
 
-    Ok(())
-}
+        let extracted_dir = tempdir().unwrap();
+        let names = [
+            "project/src/bin/tool.sh".to_string(),
+            "project/docs/faq.md".to_string(),
+            "project/missing.txt".to_string(),
+        ];
+        let found = extract_entries(&zip_path, &names, extracted_dir.path(), false).unwrap();
+        assert_eq!(found, names[..2]);
 
-#[pyfunction]
-#[pyo3(name = "unzip_files")]
-pub fn unzip_files_pywrapper(src_py: String, dst_py: String) -> PyResult<()> {
-    let src_path = PathBuf::from(src_py);
-    let dst_path = PathBuf::from(dst_py);
+        let root = extracted_dir.path().join("project");
+        let mut extracted: Vec<String> = walkdir::WalkDir::new(&root)
+            .into_iter()
+            .map(|e| {
+                let e = e.unwrap();
+                e.path()
+                    .strip_prefix(&root)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        extracted.sort();
+        assert_eq!(
+            extracted,
+            [
+                "",
+                "docs",
+                "docs/faq.md",
+                "src",
+                "src/bin",
+                "src/bin/tool.sh"
+            ]
+        );
+        let mode = fs::metadata(root.join("src/bin/tool.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
 
-    unzip_files(&src_path, &dst_path).map_err(|e| PyIOError::new_err(e.to_string()))
-}
+        let err = extract_entries(&zip_path, &names, extracted_dir.path(), true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*; // For unzip_files (PyO3 wrapper) and do_unzip_internal
-    use crate::zip::{zip_files, Compression};
-    use std::fs::{self};
-    use std::io::Read as StdRead;
-    use std::os::unix::fs::PermissionsExt as OsUnixPermissionsExt;
-    use tempfile::tempdir;
+    #[test]
+    fn test_embed_metadata_is_surfaced_by_archive_info() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("a.txt"), "alpha").unwrap();
 
-    // Helper to call the internal unzip function for tests that want io::Result
-    fn unzip_files_internal_wrapper(src: &Path, dst: &Path) -> io::Result<()> {
-        super::unzip_files(src, dst)
-    }
+        let tagged = original_dir.path().join("tagged.zip");
+        let options = ZipOptions {
+            embed_metadata: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&tagged, std::slice::from_ref(&project), &options).unwrap();
+        let info = archive_info(&tagged).unwrap();
+        let metadata = info.metadata.expect("metadata in the comment");
+        assert_eq!(metadata.tool, "ziprs");
+        assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
+        assert!(metadata.created > 0);
+        let parsed: serde_json::Value = serde_json::from_str(&info.comment).unwrap();
+        assert_eq!(parsed["tool"], "ziprs");
 
-    // Helper to call the PyO3 wrapped unzip function
-    fn unzip_files_py_wrapper_local(src: String, dst: String) -> PyResult<()> {
-        super::unzip_files_pywrapper(src, dst)
+        let plain = original_dir.path().join("plain.zip");
+        zip_files(&plain, &[project], Compression::Deflate).unwrap();
+        let info = archive_info(&plain).unwrap();
+        assert_eq!(info.metadata, None);
+        assert_eq!(info.comment, "");
     }
 
     #[test]
-    fn test_unzip_files_basic() {
+    fn test_unzip_backup_keeps_replaced_file() {
         let original_dir = tempdir().unwrap();
-        let zip_file_path = original_dir.path().join("archive.zip");
-        let extracted_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("config.txt"), "archived").unwrap();
+        let zip_path = original_dir.path().join("config.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
 
-        let file1_path = original_dir.path().join("file1.txt");
-        let subdir_path = original_dir.path().join("subdir");
-        let subfile_path = subdir_path.join("subfile.txt");
+        let extracted_dir = tempdir().unwrap();
+        let target = extracted_dir.path().join("project/config.txt");
+        let backup = extracted_dir.path().join("project/config.txt.bak");
+        fs::create_dir(extracted_dir.path().join("project")).unwrap();
+        fs::write(&target, "local edits").unwrap();
 
-        fs::write(&file1_path, "hello from file1").unwrap();
-        fs::create_dir(&subdir_path).unwrap();
-        fs::write(&subfile_path, "hello from subfile").unwrap();
+        let options = UnzipOptions {
+            backup: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "archived");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "local edits");
 
-        let mut perms_file1 = fs::metadata(&file1_path).unwrap().permissions();
-        // Use the explicitly imported trait for set_mode
-        OsUnixPermissionsExt::set_mode(&mut perms_file1, 0o644);
-        fs::set_permissions(&file1_path, perms_file1).unwrap();
+        // A second run replaces the backup rather than piling up more of them
+        fs::write(&target, "newer edits").unwrap();
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "archived");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "newer edits");
+        assert!(!extracted_dir
+            .path()
+            .join("project/config.txt.bak.bak")
+            .exists());
+    }
 
-        let mut perms_subfile = fs::metadata(&subfile_path).unwrap().permissions();
-        // Use the explicitly imported trait for set_mode
-        OsUnixPermissionsExt::set_mode(&mut perms_subfile, 0o755);
-        fs::set_permissions(&subfile_path, perms_subfile).unwrap();
+    #[test]
+    fn test_verify_manifest_flags_modified_entries() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("a.txt"), "alpha").unwrap();
+        fs::write(project.join("b.txt"), "beta").unwrap();
+        let zip_path = original_dir.path().join("signed.zip");
+        let options = ZipOptions {
+            write_manifest: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_path, &[project], &options).unwrap();
+        assert!(verify_manifest(&zip_path).unwrap().is_empty());
 
-        let srcs_to_zip_str = vec![
-            file1_path.to_str().unwrap().to_string(),
-            subdir_path.to_str().unwrap().to_string(),
-        ];
-        let srcs_to_zip_pathbuf: Vec<PathBuf> =
-            srcs_to_zip_str.into_iter().map(PathBuf::from).collect();
+        // Rebuild the archive with new content for one entry, copying the rest (manifest
+        // included) as they are
+        let tampered_path = original_dir.path().join("tampered.zip");
+        let mut archive = ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+        let mut writer = zip::ZipWriter::new(fs::File::create(&tampered_path).unwrap());
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i).unwrap();
+            if entry.name() == "project/a.txt" {
+                drop(entry);
+                writer
+                    .start_file("project/a.txt", zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                writer.write_all(b"ALPHA").unwrap();
+            } else {
+                writer.raw_copy_file(entry).unwrap();
+            }
+        }
+        writer.finish().unwrap();
 
-        // Call the internal zip_files function directly
-        zip_files(&zip_file_path, &srcs_to_zip_pathbuf, Compression::default()).unwrap();
+        assert_eq!(
+            verify_manifest(&tampered_path).unwrap(),
+            vec!["project/a.txt".to_string()]
+        );
 
-        // Test the PyO3 wrapper for unzipping
-        unzip_files_py_wrapper_local(
-            zip_file_path.to_str().unwrap().to_string(),
-            extracted_dir.path().to_str().unwrap().to_string(),
+        // Archives written without a manifest can't be verified
+        let plain_path = original_dir.path().join("plain.zip");
+        zip_files(
+            &plain_path,
+            &[original_dir.path().join("project")],
+            Compression::Deflate,
         )
         .unwrap();
+        assert_eq!(
+            verify_manifest(&plain_path).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
 
-        let extracted_file1 = extracted_dir.path().join("file1.txt");
-        let extracted_subdir = extracted_dir.path().join("subdir");
-        let extracted_subfile = extracted_subdir.join("subfile.txt");
-
-        assert!(extracted_file1.exists());
-        assert!(extracted_subdir.is_dir());
-        assert!(extracted_subfile.exists());
-
-        let mut content_file1 = String::new();
-        fs::File::open(&extracted_file1)
-            .unwrap()
-            .read_to_string(&mut content_file1)
-            .unwrap();
-        assert_eq!(content_file1, "hello from file1");
-
-        let mut content_subfile = String::new();
-        fs::File::open(&extracted_subfile)
-            .unwrap()
-            .read_to_string(&mut content_subfile)
-            .unwrap();
-        assert_eq!(content_subfile, "hello from subfile");
+    #[test]
+    fn test_unzip_by_dir_order_extracts_everything() {
+        // An archive that interleaves two directories
+        let original_dir = tempdir().unwrap();
+        let zip_path = original_dir.path().join("interleaved.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        let file_options = zip::write::SimpleFileOptions::default();
+        for name in [
+            "a/1.txt",
+            "b/1.txt",
+            "a/sub/1.txt",
+            "a/2.txt",
+            "b/2.txt",
+            "top.txt",
+        ] {
+            writer.start_file(name, file_options).unwrap();
+            writer.write_all(name.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
 
-        #[cfg(unix)]
-        {
-            // Use the explicitly imported trait for mode()
-            let perms_ext_file1 =
-                OsUnixPermissionsExt::mode(&fs::metadata(&extracted_file1).unwrap().permissions());
-            assert_eq!(perms_ext_file1 & 0o777, 0o644);
+        let archive = ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+        let names: Vec<&str> = ExtractOrder::ByDir
+            .arrange(&archive)
+            .into_iter()
+            .map(|i| archive.name_for_index(i).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "top.txt",
+                "a/1.txt",
+                "a/2.txt",
+                "a/sub/1.txt",
+                "b/1.txt",
+                "b/2.txt"
+            ]
+        );
 
-            let perms_ext_subfile = OsUnixPermissionsExt::mode(
-                &fs::metadata(&extracted_subfile).unwrap().permissions(),
+        let extracted_dir = tempdir().unwrap();
+        let options = UnzipOptions {
+            order: ExtractOrder::ByDir,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &options).unwrap();
+        for name in [
+            "a/1.txt",
+            "b/1.txt",
+            "a/sub/1.txt",
+            "a/2.txt",
+            "b/2.txt",
+            "top.txt",
+        ] {
+            assert_eq!(
+                fs::read_to_string(extracted_dir.path().join(name)).unwrap(),
+                name
             );
-            assert_eq!(perms_ext_subfile & 0o777, 0o755);
         }
-
-        // Optionally, test internal unzip function
-        let extracted_dir_internal = tempdir().unwrap();
-        unzip_files_internal_wrapper(&zip_file_path, extracted_dir_internal.path()).unwrap();
-        // Add assertions for internal version similar to above
-        assert!(extracted_dir_internal.path().join("file1.txt").exists());
     }
 
     #[test]
-    fn test_unzip_to_non_existent_destination() {
+    fn test_encrypted_round_trip_with_each_method() {
         let original_dir = tempdir().unwrap();
-        let zip_file_path = original_dir.path().join("archive.zip");
-        let extracted_base_dir = tempdir().unwrap();
-        let extracted_dir_path = extracted_base_dir.path().join("new_dest_dir");
+        let project = original_dir.path().join("project");
+        fs::create_dir_all(project.join("docs")).unwrap();
+        fs::write(project.join("secret.txt"), "attack at dawn").unwrap();
+        fs::write(project.join("docs/notes.md"), "# notes\n".repeat(50)).unwrap();
 
-        let file1_path = original_dir.path().join("dummy.txt");
-        fs::write(&file1_path, "dummy content").unwrap();
-        let srcs_to_zip_str = vec![file1_path.to_str().unwrap().to_string()];
-        let srcs_to_zip_pathbuf: Vec<PathBuf> =
-            srcs_to_zip_str.into_iter().map(PathBuf::from).collect();
+        for method in [EncryptionMethod::Aes256, EncryptionMethod::ZipCrypto] {
+            let zip_path = original_dir.path().join(format!("{:?}.zip", method));
+            let options = ZipOptions {
+                password: Some("hunter2".to_string()),
+                encryption_method: method,
+                ..Default::default()
+            };
+            zip_files_with_options(&zip_path, std::slice::from_ref(&project), &options).unwrap();
 
-        zip_files(&zip_file_path, &srcs_to_zip_pathbuf, Compression::default()).unwrap();
+            // Any zip reader can decrypt the entries, and nothing is readable without the password
+            let mut archive = ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+            assert!(archive.by_index_raw(0).unwrap().is_dir());
+            let secret_index = archive.index_for_name("project/secret.txt").unwrap();
+            assert!(archive.by_index_raw(secret_index).unwrap().encrypted());
+            let mut content = String::new();
+            archive
+                .by_name_decrypt("project/secret.txt", b"hunter2")
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, "attack at dawn");
+            assert!(archive
+                .by_name_decrypt("project/secret.txt", b"wrong")
+                .is_err());
 
-        let result = unzip_files_py_wrapper_local(
-            zip_file_path.to_str().unwrap().to_string(),
-            extracted_dir_path.to_str().unwrap().to_string(),
+            let locked_dir = tempdir().unwrap();
+            assert!(unzip_files_internal_wrapper(&zip_path, locked_dir.path()).is_err());
+
+            let extracted_dir = tempdir().unwrap();
+            let unzip_options = UnzipOptions {
+                password: Some("hunter2".to_string()),
+                ..Default::default()
+            };
+            unzip_files_with_options(&zip_path, extracted_dir.path(), &unzip_options).unwrap();
+            assert_eq!(
+                fs::read_to_string(extracted_dir.path().join("project/secret.txt")).unwrap(),
+                "attack at dawn"
+            );
+            assert_eq!(
+                fs::read_to_string(extracted_dir.path().join("project/docs/notes.md")).unwrap(),
+                "# notes\n".repeat(50)
+            );
+        }
+    }
+
+    #[test]
+    fn test_unzip_many_namespaced() {
+        let dir = tempdir().unwrap();
+        let mut zips = Vec::new();
+        for name in ["first", "second"] {
+            // Same layout in both, so they'd overwrite each other without namespacing
+            let src = dir.path().join(name);
+            fs::create_dir(&src).unwrap();
+            fs::write(src.join("data.txt"), name).unwrap();
+            let zip_path = dir.path().join(format!("{}.zip", name));
+            zip_files(&zip_path, &[src.join("data.txt")], Compression::Deflate).unwrap();
+            zips.push(zip_path);
+        }
+
+        let dst = dir.path().join("out");
+        let dst_paths = unzip_many(&zips, &dst, true).unwrap();
+        assert_eq!(dst_paths, vec![dst.join("first"), dst.join("second")]);
+        assert_eq!(
+            fs::read_to_string(dst.join("first/data.txt")).unwrap(),
+            "first"
         );
-        assert!(result.is_ok());
-        assert!(extracted_dir_path.exists() && extracted_dir_path.is_dir());
-        assert!(extracted_dir_path.join("dummy.txt").exists());
+        assert_eq!(
+            fs::read_to_string(dst.join("second/data.txt")).unwrap(),
+            "second"
+        );
+        assert!(!dst.join("data.txt").exists());
+
+        // The same archive twice can't be namespaced apart
+        let err = unzip_many(&[zips[0].clone(), zips[0].clone()], &dst, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
     }
 
     #[test]
-    fn test_unzip_empty_directory() {
-        let original_dir = tempdir().unwrap();
-        let zip_file_path = original_dir.path().join("archive.zip");
+    fn test_malformed_central_directory_is_rejected() {
+        let dir = tempdir().unwrap();
         let extracted_dir = tempdir().unwrap();
 
-        let empty_dir_src = original_dir.path().join("empty_folder");
-        fs::create_dir(&empty_dir_src).unwrap();
+        // End of central directory records alone, describing directories that can't exist
+        let end_record = |entries: u16, size: u32| {
+            let mut record = b"PK\x05\x06\0\0\0\0".to_vec();
+            record.extend_from_slice(&entries.to_le_bytes());
+            record.extend_from_slice(&entries.to_le_bytes());
+            record.extend_from_slice(&size.to_le_bytes());
+            record.extend_from_slice(&[0; 6]);
+            record
+        };
+        let crowded = dir.path().join("crowded.zip");
+        fs::write(&crowded, end_record(0xfffe, 0)).unwrap();
+        let err = unzip_files_internal_wrapper(&crowded, extracted_dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string()
+                .contains("malformed central directory: it claims 65534 entries in 0 bytes"),
+            "{}",
+            err
+        );
 
-        let srcs_to_zip_str = vec![empty_dir_src.to_str().unwrap().to_string()];
-        let srcs_to_zip_pathbuf: Vec<PathBuf> =
-            srcs_to_zip_str.into_iter().map(PathBuf::from).collect();
-        zip_files(&zip_file_path, &srcs_to_zip_pathbuf, Compression::default()).unwrap();
+        let oversized = dir.path().join("oversized.zip");
+        fs::write(&oversized, end_record(1, 0xffff_0000)).unwrap();
+        let err = unzip_files_internal_wrapper(&oversized, extracted_dir.path()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("it claims 4294901760 bytes in a file of 22 bytes"),
+            "{}",
+            err
+        );
 
-        unzip_files_py_wrapper_local(
-            zip_file_path.to_str().unwrap().to_string(),
+        let long_name = dir.path().join("long_name.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&long_name).unwrap());
+        writer
+            .start_file("a".repeat(5000), zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.finish().unwrap();
+        let err = unzip_files_internal_wrapper(&long_name, extracted_dir.path()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("entry name of 5000 bytes, over the limit of 4096"),
+            "{}",
+            err
+        );
+        assert_eq!(fs::read_dir(extracted_dir.path()).unwrap().count(), 0);
+
+        pyo3::prepare_freethreaded_python();
+        let err = unzip_files_py_wrapper_local(
+            crowded.to_str().unwrap().to_string(),
             extracted_dir.path().to_str().unwrap().to_string(),
         )
+        .unwrap_err();
+        Python::with_gil(|py| assert!(err.is_instance_of::<PyIOError>(py)));
+        assert!(
+            err.to_string().contains("malformed central directory"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_unzip_creates_files_with_their_mode() {
+        let temp_dir = tempdir().unwrap();
+        let zip_path = temp_dir.path().join("secret.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        writer
+            .start_file(
+                "secret.key",
+                zip::write::SimpleFileOptions::default().unix_permissions(0o600),
+            )
+            .unwrap();
+        writer.write_all(b"private").unwrap();
+        writer.finish().unwrap();
+
+        // The file is created with the mode instead of getting it once written
+        let created = temp_dir.path().join("created.key");
+        drop(create_output_file(&created, Some(0o600)).unwrap());
+        let mode = fs::metadata(&created).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let out = temp_dir.path().join("out");
+        unzip_files(&zip_path, &out).unwrap();
+        let extracted = out.join("secret.key");
+        assert_eq!(fs::read(&extracted).unwrap(), b"private");
+        let mode = fs::metadata(&extracted).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_read_entry_lines_in_order_and_early_stop() {
+        let temp_dir = tempdir().unwrap();
+        let project = temp_dir.path().join("logs");
+        fs::create_dir(&project).unwrap();
+        let lines: Vec<String> = (0..5_000).map(|i| format!("request {} ok", i)).collect();
+        fs::write(project.join("app.log"), lines.join("\n") + "\r\n").unwrap();
+        let zip_path = temp_dir.path().join("logs.zip");
+        zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+        let mut seen = Vec::new();
+        let visited = read_entry_lines(&zip_path, "logs/app.log", |line| {
+            seen.push(line.to_string());
+            Ok(true)
+        })
         .unwrap();
+        assert_eq!(visited, lines.len());
+        assert_eq!(seen, lines);
 
-        let extracted_empty_dir = extracted_dir.path().join("empty_folder");
-        assert!(extracted_empty_dir.exists() && extracted_empty_dir.is_dir());
-        // Check if it's actually empty
-        assert_eq!(fs::read_dir(&extracted_empty_dir).unwrap().count(), 0);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let collected = pyo3::types::PyList::empty(py);
+            let globals = pyo3::types::PyDict::new(py);
+            globals.set_item("seen", &collected).unwrap();
+            let stop_early = py
+                .eval(
+                    c"lambda line: seen.append(line) or len(seen) < 3",
+                    Some(&globals),
+                    None,
+                )
+                .unwrap()
+                .unbind();
+            let visited = read_entry_lines_pywrapper(
+                zip_path.to_str().unwrap().to_string(),
+                "logs/app.log".to_string(),
+                stop_early,
+            )
+            .unwrap();
+            assert_eq!(visited, 3);
+            let collected: Vec<String> = collected.extract().unwrap();
+            assert_eq!(collected, lines[..3]);
+        });
+
+        let err = read_entry_lines(&zip_path, "logs/missing.log", |_| Ok(true)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_birthtime_round_trip() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        let file = project.join("born.txt");
+        fs::write(&file, "born").unwrap();
+        let created = fs::metadata(&file).unwrap().created().unwrap();
+
+        let zip_path = original_dir.path().join("birthtime.zip");
+        let zip_options = ZipOptions {
+            preserve_birthtime: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_path, &[project], &zip_options).unwrap();
+        // Make sure the extracted file would be born later without the option
+        std::thread::sleep(Duration::from_millis(20));
+
+        let extracted_dir = tempdir().unwrap();
+        let unzip_options = UnzipOptions {
+            preserve_birthtime: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &unzip_options).unwrap();
+        let extracted = extracted_dir.path().join("project/born.txt");
+        assert_eq!(
+            fs::metadata(&extracted).unwrap().created().unwrap(),
+            created
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_birthtime_ignored_on_linux() {
+        let original_dir = tempdir().unwrap();
+        let project = original_dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        let file = project.join("born.txt");
+        fs::write(&file, "born").unwrap();
+        let reported = fs::metadata(&file).unwrap().created().is_ok();
+
+        let zip_path = original_dir.path().join("birthtime.zip");
+        let zip_options = ZipOptions {
+            preserve_birthtime: true,
+            ..Default::default()
+        };
+        zip_files_with_options(&zip_path, &[project], &zip_options).unwrap();
+        let mut archive = ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+        let entry = archive.by_name("project/born.txt").unwrap();
+        let recorded = entry
+            .extra_data()
+            .and_then(|extra| find_field(extra, ZIPRS_BIRTHTIME_ID))
+            .and_then(decode_birthtime)
+            .is_some();
+        // Recorded whenever the file system reports a creation time
+        assert_eq!(recorded, reported);
+        drop(entry);
+
+        // Restoring it is a no-op that doesn't fail the extraction
+        let extracted_dir = tempdir().unwrap();
+        let unzip_options = UnzipOptions {
+            preserve_birthtime: true,
+            ..Default::default()
+        };
+        unzip_files_with_options(&zip_path, extracted_dir.path(), &unzip_options).unwrap();
+        assert_eq!(
+            fs::read(extracted_dir.path().join("project/born.txt")).unwrap(),
+            b"born"
+        );
     }
 }
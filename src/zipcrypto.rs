@@ -0,0 +1,199 @@
+// Traditional PKWARE encryption ("ZipCrypto"), applied to a finished archive for
+// `EncryptionMethod::ZipCrypto`. The zip crate decrypts it but doesn't expose writing it.
+//
+// ZipCrypto is broken: a dozen bytes of known plaintext, such as the usual start of a file
+// format, recover the keys in minutes whatever the password. It only exists for consumers
+// that can't read AES entries and must never be relied on to keep anything secret.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::central_directory::{
+    invalid, read_u16, read_u32, record_name, unsupported, write_u16, write_u32, CentralDirectory,
+};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+
+// Length of the random header every encrypted entry's data starts with
+pub(crate) const ENCRYPTION_HEADER_LEN: u32 = 12;
+
+// Table of the CRC-32 the key schedule is built on, the same polynomial zip uses for content
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            bit += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    (crc >> 8) ^ CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize]
+}
+
+// The three keys of the cipher, as laid out in APPNOTE section 6.1
+struct Keys(u32, u32, u32);
+
+impl Keys {
+    fn new(password: &[u8]) -> Self {
+        let mut keys = Keys(0x1234_5678, 0x2345_6789, 0x3456_7890);
+        for &byte in password {
+            keys.update(byte);
+        }
+        keys
+    }
+
+    fn update(&mut self, plain: u8) {
+        self.0 = crc32_update(self.0, plain);
+        self.1 = self
+            .1
+            .wrapping_add(self.0 & 0xff)
+            .wrapping_mul(134_775_813)
+            .wrapping_add(1);
+        self.2 = crc32_update(self.2, (self.1 >> 24) as u8);
+    }
+
+    fn encrypt(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            let temp = (self.2 | 2) as u16;
+            let key = (temp.wrapping_mul(temp ^ 1) >> 8) as u8;
+            let plain = *byte;
+            self.update(plain);
+            *byte = plain ^ key;
+        }
+    }
+}
+
+// Fill `buf` from the OS random source: getrandom on Linux, getentropy on macOS
+fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    getrandom::fill(buf)?;
+    Ok(())
+}
+
+// Rewrite the finished archive at `path` with the data of every file entry encrypted under
+// `password`. Directory entries, which hold no data, are left as they are. Archives that
+// need zip64 records (4 GiB or more) are refused, as are entries using data descriptors,
+// which ziprs never writes.
+pub(crate) fn encrypt_archive(path: &Path, password: &str) -> io::Result<()> {
+    let mut source = BufReader::new(File::open(path)?);
+    let central_directory_start = ZipArchive::new(source.get_mut())?.central_directory_start();
+    let directory = CentralDirectory::read(&mut source, central_directory_start)?;
+    if directory.is_zip64() {
+        return Err(unsupported(
+            "ZipCrypto is only supported for archives under 4 GiB",
+        ));
+    }
+
+    let temp_file = tempfile::NamedTempFile::new_in(path.parent().unwrap_or(Path::new(".")))?;
+    let mut output = BufWriter::new(temp_file.as_file());
+    let mut written: u64 = 0;
+    let mut chunk = vec![0u8; 64 * 1024];
+    let CentralDirectory {
+        mut records,
+        mut end_records,
+    } = directory;
+    for record in &mut records {
+        let flags = read_u16(record, 8);
+        let crc32 = read_u32(record, 16);
+        let compressed_size = read_u32(record, 20);
+        let local_offset = read_u32(record, 42);
+        if compressed_size == u32::MAX || local_offset == u32::MAX {
+            return Err(unsupported(
+                "ZipCrypto is only supported for archives under 4 GiB",
+            ));
+        }
+        if flags & (1 << 3) != 0 {
+            return Err(unsupported(
+                "Entries with data descriptors can't be encrypted",
+            ));
+        }
+        let is_dir = record_name(record).ends_with(b"/");
+
+        let mut local_header = [0u8; 30];
+        source.seek(SeekFrom::Start(u64::from(local_offset)))?;
+        source.read_exact(&mut local_header)?;
+        if read_u32(&local_header, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(invalid("Missing local file header"));
+        }
+        let mut name_and_extra =
+            vec![0u8; read_u16(&local_header, 26) as usize + read_u16(&local_header, 28) as usize];
+        source.read_exact(&mut name_and_extra)?;
+
+        let new_offset = u32::try_from(written)
+            .ok()
+            .filter(|&offset| offset != u32::MAX)
+            .ok_or_else(|| unsupported("ZipCrypto is only supported for archives under 4 GiB"))?;
+        write_u32(record, 42, new_offset);
+        let mut keys = Keys::new(password.as_bytes());
+        let encrypted_size = if is_dir {
+            compressed_size
+        } else {
+            let encrypted_size = compressed_size
+                .checked_add(ENCRYPTION_HEADER_LEN)
+                .filter(|&size| size != u32::MAX)
+                .ok_or_else(|| {
+                    unsupported("ZipCrypto is only supported for entries under 4 GiB")
+                })?;
+            write_u16(&mut local_header, 6, flags | 1);
+            write_u16(record, 8, flags | 1);
+            write_u32(&mut local_header, 18, encrypted_size);
+            write_u32(record, 20, encrypted_size);
+            encrypted_size
+        };
+        output.write_all(&local_header)?;
+        output.write_all(&name_and_extra)?;
+
+        if !is_dir {
+            // Random bytes, then the high byte of the CRC for readers to check the password
+            let mut encryption_header = [0u8; ENCRYPTION_HEADER_LEN as usize];
+            random_bytes(&mut encryption_header[..11])?;
+            encryption_header[11] = (crc32 >> 24) as u8;
+            keys.encrypt(&mut encryption_header);
+            output.write_all(&encryption_header)?;
+        }
+        let mut remaining = u64::from(compressed_size);
+        while remaining > 0 {
+            let len = remaining.min(chunk.len() as u64) as usize;
+            source.read_exact(&mut chunk[..len])?;
+            if !is_dir {
+                keys.encrypt(&mut chunk[..len]);
+            }
+            output.write_all(&chunk[..len])?;
+            remaining -= len as u64;
+        }
+        written += (local_header.len() + name_and_extra.len()) as u64 + u64::from(encrypted_size);
+    }
+
+    // Only the offset of the central directory moves; its records keep their length
+    let central_directory_offset = u32::try_from(written)
+        .map_err(|_| unsupported("ZipCrypto is only supported for archives under 4 GiB"))?;
+    let offset_field = end_records
+        .get_mut(16..20)
+        .ok_or_else(|| invalid("Truncated end of central directory record"))?;
+    offset_field.copy_from_slice(&central_directory_offset.to_le_bytes());
+    for record in &records {
+        output.write_all(record)?;
+    }
+    output.write_all(&end_records)?;
+    output.flush()?;
+    drop(output);
+
+    fs::set_permissions(temp_file.path(), fs::metadata(path)?.permissions())?;
+    temp_file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+use tempfile::tempdir;
+use ziprs::zip::{zip_files, Compression};
+
+#[test]
+fn test_info_prints_entry_count() {
+    let dir = tempdir().unwrap();
+    let project = dir.path().join("project");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join("one.txt"), "one").unwrap();
+    fs::write(project.join("two.txt"), "two").unwrap();
+    let zip_path = dir.path().join("known.zip");
+    zip_files(&zip_path, &[project], Compression::Deflate).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .arg("info")
+        .arg(&zip_path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // The directory entry plus two files
+    assert!(
+        stdout.lines().any(|line| line == "Entries:           3"),
+        "unexpected output:\n{}",
+        stdout
+    );
+    assert!(stdout.contains("Comment:           (none)"));
+}
+
+#[test]
+fn test_unzip_stdout_writes_entry_bytes() {
+    let dir = tempdir().unwrap();
+    let project = dir.path().join("project");
+    fs::create_dir(&project).unwrap();
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(project.join("data.bin"), &content).unwrap();
+    fs::write(project.join("other.txt"), "other").unwrap();
+    let zip_path = dir.path().join("piped.zip");
+    zip_files(
+        &zip_path,
+        std::slice::from_ref(&project),
+        Compression::Deflate,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .args(["unzip", "--stdout"])
+        .arg(&zip_path)
+        .arg("project/data.bin")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, content);
+
+    // Without a name, --stdout needs an archive holding a single file
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .args(["unzip", "--stdout"])
+        .arg(&zip_path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let single_path = dir.path().join("single.zip");
+    zip_files(
+        &single_path,
+        &[project.join("other.txt")],
+        Compression::Deflate,
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .args(["unzip", "--stdout"])
+        .arg(&single_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"other");
+}
+
+#[test]
+fn test_list_tree_shows_implied_directories() {
+    let dir = tempdir().unwrap();
+    let zip_path = dir.path().join("nested.zip");
+    // No explicit directory entries: every directory is implied by a file path
+    let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    for name in [
+        "project/nested/deep/b.txt",
+        "project/a.txt",
+        "project/nested/c.txt",
+        "top.txt",
+    ] {
+        zip.start_file(name, options).unwrap();
+        zip.write_all(name.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .args(["list", "--tree"])
+        .arg(&zip_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected = [
+        "project/",
+        "├── a.txt",
+        "└── nested/",
+        "    ├── c.txt",
+        "    └── deep/",
+        "        └── b.txt",
+        "top.txt",
+    ];
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected, "{}", stdout);
+}
+
+#[test]
+fn test_zip_exclude_leaves_out_matching_files() {
+    let dir = tempdir().unwrap();
+    let project = dir.path().join("project");
+    fs::create_dir_all(project.join("logs")).unwrap();
+    fs::write(project.join("main.rs"), "fn main() {}").unwrap();
+    fs::write(project.join("debug.log"), "noise").unwrap();
+    fs::write(project.join("logs/today.log"), "noise").unwrap();
+    let zip_path = dir.path().join("excluded.zip");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .args(["zip", "--compression", "deflate", "--exclude", "*.log"])
+        .arg("--output-path")
+        .arg(&zip_path)
+        .arg(&project)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let archive = zip::ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+    let mut names: Vec<&str> = archive.file_names().collect();
+    names.sort();
+    assert_eq!(names, ["project/", "project/logs/", "project/main.rs"]);
+}
+
+#[test]
+fn test_zip_password_env_encrypts_entries() {
+    let dir = tempdir().unwrap();
+    let project = dir.path().join("project");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join("secret.txt"), "attack at dawn").unwrap();
+    let zip_path = dir.path().join("encrypted.zip");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .args(["zip", "--compression", "deflate", "--password-env"])
+        .env("ZIPRS_PASSWORD", "hunter2")
+        .arg("--output-path")
+        .arg(&zip_path)
+        .arg(&project)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+    assert!(archive.by_name("project/secret.txt").is_err());
+    let mut content = String::new();
+    archive
+        .by_name_decrypt("project/secret.txt", b"hunter2")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "attack at dawn");
+
+    // --encrypt without any password only prompts from a terminal, and is refused otherwise
+    let output = Command::new(env!("CARGO_BIN_EXE_ziprs"))
+        .args(["zip", "--compression", "deflate", "--encrypt"])
+        .env_remove("ZIPRS_PASSWORD")
+        .arg("--output-path")
+        .arg(dir.path().join("refused.zip"))
+        .arg(&project)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--encrypt needs a password"));
+}